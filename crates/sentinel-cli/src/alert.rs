@@ -0,0 +1,453 @@
+//! Alert delivery. Every alert is printed to stderr and handed to each
+//! configured [`AlertSink`].
+
+use async_trait::async_trait;
+use clap::Args;
+use nostr_sdk::prelude::*;
+use sentinel_core::event_parser::ParsedLocation;
+use sentinel_core::geofence::{Transition, TransitionKind};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::follow::{format_secs, Target};
+use crate::parse_duration_str;
+use crate::relay_health::RelayEvent;
+
+pub use format::{Template, WebhookFormat};
+pub use http::{Delivery, DeliveryOptions};
+
+pub mod desktop;
+mod email;
+mod exec;
+mod format;
+mod http;
+mod nostr_dm;
+
+/// An alert raised while following.
+#[derive(Debug, Clone)]
+pub enum Alert {
+    /// No fresh location from `target`. `degraded` means no relay was
+    /// connected while it was silent, so the cause may be on our side.
+    Silence {
+        target: Target,
+        last_seen: Option<Timestamp>,
+        /// Last decoded location, if any.
+        location: Option<ParsedLocation>,
+        silent_secs: u64,
+        repeat: u32,
+        degraded: bool,
+    },
+    /// Updates from `target` resumed after an alert.
+    Recovered {
+        target: Target,
+        silent_secs: u64,
+        location: Option<ParsedLocation>,
+    },
+    /// `target` crossed a geofence.
+    Geofence { target: Target, transition: Transition },
+    /// `relay` has been disconnected since `since`.
+    RelayDown { relay: String, since: Timestamp },
+    /// `relay` reconnected after being reported down.
+    RelayUp { relay: String, down_secs: u64 },
+}
+
+impl From<RelayEvent> for Alert {
+    fn from(event: RelayEvent) -> Self {
+        match event {
+            RelayEvent::Down { relay, since } => Alert::RelayDown { relay, since },
+            RelayEvent::Up { relay, down_secs } => Alert::RelayUp { relay, down_secs },
+        }
+    }
+}
+
+impl Alert {
+    /// Alert type as used in webhook bodies, e.g. `silence`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Alert::Silence { .. } => "silence",
+            Alert::Recovered { .. } => "recovered",
+            Alert::Geofence { .. } => "geofence",
+            Alert::RelayDown { .. } => "relay_down",
+            Alert::RelayUp { .. } => "relay_up",
+        }
+    }
+
+    /// Whether the alert reports something going wrong (as opposed to a recovery
+    /// or a geofence transition).
+    pub fn is_problem(&self) -> bool {
+        matches!(self, Alert::Silence { .. } | Alert::RelayDown { .. })
+    }
+
+    /// One-line summary for notification titles and email subjects.
+    pub fn title(&self) -> String {
+        match self {
+            Alert::Silence { target, degraded: true, .. } => {
+                format!("{} silent (relays unreachable)", target.label())
+            }
+            Alert::Silence { target, silent_secs, .. } => {
+                format!("{} silent for {}", target.label(), format_secs(*silent_secs))
+            }
+            Alert::Recovered { target, .. } => format!("{} back online", target.label()),
+            Alert::Geofence { target, transition } => {
+                format!("{} {} {}", target.label(), transition_verb(transition.kind), transition.zone)
+            }
+            Alert::RelayDown { relay, .. } => format!("Relay {} unreachable", relay),
+            Alert::RelayUp { relay, .. } => format!("Relay {} reconnected", relay),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Alert::Silence { target, last_seen, silent_secs, repeat, degraded, .. } => {
+                let last = match last_seen {
+                    Some(ts) => format!("last seen {}", ts.to_human_datetime()),
+                    None => "never seen".to_string(),
+                };
+                let again = if *repeat > 1 { format!(" (alert #{})", repeat) } else { String::new() };
+                let (prefix, note) = match degraded {
+                    true => ("DEGRADED", " — no relay was reachable, may be our network"),
+                    false => ("ALERT", ""),
+                };
+                format!(
+                    "{}: No location update from {} for {} ({}){}{}",
+                    prefix,
+                    target.label(),
+                    format_secs(*silent_secs),
+                    last,
+                    again,
+                    note,
+                )
+            }
+            Alert::Recovered { target, silent_secs, .. } => format!(
+                "RECOVERED: Location updates from {} resumed after {}",
+                target.label(),
+                format_secs(*silent_secs),
+            ),
+            Alert::Geofence { target, transition } => {
+                let loc = &transition.location;
+                format!(
+                    "ALERT: {} {} {} at {} ({:.6}, {:.6})",
+                    target.label(),
+                    transition_verb(transition.kind),
+                    transition.zone,
+                    loc.timestamp.to_human_datetime(),
+                    loc.lat,
+                    loc.lon,
+                )
+            }
+            Alert::RelayDown { relay, since } => format!(
+                "ALERT: Relay {} unreachable since {}",
+                relay,
+                since.to_human_datetime(),
+            ),
+            Alert::RelayUp { relay, down_secs } => format!(
+                "RECOVERED: Relay {} reconnected after {}",
+                relay,
+                format_secs(*down_secs),
+            ),
+        }
+    }
+
+    /// The location the alert is about: the last known one for silence and
+    /// recovery, the crossing for geofences.
+    pub fn location(&self) -> Option<&ParsedLocation> {
+        match self {
+            Alert::Silence { location, .. } | Alert::Recovered { location, .. } => location.as_ref(),
+            Alert::Geofence { transition, .. } => Some(&transition.location),
+            Alert::RelayDown { .. } | Alert::RelayUp { .. } => None,
+        }
+    }
+
+    /// The followed target, or `None` for relay alerts.
+    pub fn target(&self) -> Option<&Target> {
+        match self {
+            Alert::Silence { target, .. }
+            | Alert::Recovered { target, .. }
+            | Alert::Geofence { target, .. } => Some(target),
+            Alert::RelayDown { .. } | Alert::RelayUp { .. } => None,
+        }
+    }
+
+    /// Generic webhook JSON body. `text` keeps Slack-style webhooks working.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut body = serde_json::json!({ "text": self.message(), "type": self.kind() });
+        if let (Some(body), Some(target)) = (body.as_object_mut(), self.target()) {
+            body.insert("name".into(), target.name.clone().into());
+            body.insert("pubkey".into(), target.pubkey.to_bech32().unwrap_or_default().into());
+            body.insert("d_tag".into(), target.d_tag.clone().into());
+        }
+        if let (Some(body), Some(loc)) = (body.as_object_mut(), self.location()) {
+            let location = serde_json::json!({
+                "lat": loc.lat,
+                "lon": loc.lon,
+                "geohash": loc.geohash,
+                "accuracy": loc.accuracy,
+                "d_tag": loc.d_tag,
+                "timestamp": loc.timestamp.as_u64(),
+            });
+            body.insert("location".into(), location);
+            body.insert("map_url".into(), map_url(loc).into());
+        }
+        let extra = match self {
+            Alert::Silence { last_seen, silent_secs, repeat, degraded, .. } => serde_json::json!({
+                "last_seen": last_seen.map(|ts| ts.as_u64()),
+                "silent_secs": silent_secs,
+                "repeat": repeat,
+                "degraded": degraded,
+            }),
+            Alert::Recovered { silent_secs, .. } => serde_json::json!({
+                "silent_secs": silent_secs,
+            }),
+            Alert::Geofence { transition, .. } => serde_json::json!({
+                "zone": transition.zone,
+                "transition": transition.kind,
+                "timestamp": transition.location.timestamp.as_u64(),
+            }),
+            Alert::RelayDown { relay, since } => serde_json::json!({
+                "relay": relay,
+                "since": since.as_u64(),
+            }),
+            Alert::RelayUp { relay, down_secs } => serde_json::json!({
+                "relay": relay,
+                "down_secs": down_secs,
+            }),
+        };
+        if let (Some(body), serde_json::Value::Object(extra)) = (body.as_object_mut(), extra) {
+            body.extend(extra);
+        }
+        body
+    }
+}
+
+/// OpenStreetMap link centred on `loc`.
+pub fn map_url(loc: &ParsedLocation) -> String {
+    format!(
+        "https://www.openstreetmap.org/?mlat={lat:.6}&mlon={lon:.6}#map=16/{lat:.6}/{lon:.6}",
+        lat = loc.lat,
+        lon = loc.lon
+    )
+}
+
+fn transition_verb(kind: TransitionKind) -> &'static str {
+    match kind {
+        TransitionKind::Enter => "entered",
+        TransitionKind::Exit => "left",
+        TransitionKind::Dwell => "is dwelling in",
+    }
+}
+
+/// Error from a single sink. Delivery to the other sinks goes on.
+#[derive(Debug, thiserror::Error)]
+pub enum SinkError {
+    #[error("Invalid sink: {0}")]
+    Config(String),
+    #[error("HTTP: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("HTTP status {0}")]
+    Status(reqwest::StatusCode),
+    #[error("I/O: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("SMTP: {0}")]
+    Smtp(String),
+    #[error("Command failed: {0}")]
+    Exec(String),
+    #[error("Nostr: {0}")]
+    Nostr(String),
+}
+
+/// A destination for alerts.
+#[async_trait]
+pub trait AlertSink: Send + Sync {
+    /// Short description for error reports, e.g. `ntfy https://ntfy.sh/topic`.
+    fn name(&self) -> String;
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError>;
+}
+
+/// What sinks may need besides their own spec.
+pub struct SinkContext<'a> {
+    pub relays: &'a [String],
+    /// Identity used to sign Nostr DMs.
+    pub keys: Option<&'a Keys>,
+    /// Body format for `webhook:` sinks.
+    pub webhook_format: &'a WebhookFormat,
+    /// Retries, signing and dead-letter log for all webhook sinks, and the
+    /// request timeout of every HTTP sink.
+    pub delivery: &'a Arc<Delivery>,
+}
+
+/// Parse a `--sink` spec:
+///
+/// - `webhook:<url>` — POST a body in the `--webhook-format`
+/// - `slack:<url>`, `discord:<url>`, `matrix:<url>` — webhook with that preset
+/// - `ntfy:<url>[?token=<token>]` — ntfy topic URL
+/// - `gotify:<url>?token=<app-token>` — Gotify server URL
+/// - `exec:<command>` — run through `sh -c`, alert in env and as JSON on stdin
+/// - `email:smtp[s]://[user:pass@]host[:port]?from=<addr>&to=<addr>[,<addr>]`
+///   (`smtp+starttls://` upgrades a plain connection)
+/// - `nostr-dm:<npub>[,<npub>]` — NIP-17 DM signed with the follower identity
+pub async fn parse_sink(spec: &str, ctx: &SinkContext<'_>) -> Result<Box<dyn AlertSink>, SinkError> {
+    let (kind, arg) = spec
+        .split_once(':')
+        .ok_or_else(|| SinkError::Config(format!("'{}' (expected <type>:<target>)", spec)))?;
+    Ok(match kind {
+        "webhook" | "slack" | "discord" | "matrix" => {
+            let format = match kind {
+                "webhook" => ctx.webhook_format.clone(),
+                preset => preset.parse()?,
+            };
+            Box::new(http::Webhook::new(arg, format, Arc::clone(ctx.delivery))?)
+        }
+        "ntfy" => Box::new(http::Ntfy::new(arg, ctx.delivery.timeout())?),
+        "gotify" => Box::new(http::Gotify::new(arg, ctx.delivery.timeout())?),
+        "exec" => Box::new(exec::Exec::new(arg)),
+        "email" => Box::new(email::Email::new(arg)?),
+        "nostr-dm" => Box::new(nostr_dm::NostrDm::new(arg, ctx).await?),
+        _ => return Err(SinkError::Config(format!("unknown sink type '{}'", kind))),
+    })
+}
+
+/// Fans alerts out to every sink.
+#[derive(Default)]
+pub struct Alerter {
+    sinks: Vec<Arc<dyn AlertSink>>,
+}
+
+impl Alerter {
+    pub async fn from_specs(specs: &[String], ctx: &SinkContext<'_>) -> Result<Self, SinkError> {
+        let mut alerter = Self::default();
+        for spec in specs {
+            alerter.sinks.push(Arc::from(parse_sink(spec, ctx).await?));
+        }
+        Ok(alerter)
+    }
+
+    pub fn add(&mut self, sink: Arc<dyn AlertSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Print the alert and send it to all sinks concurrently. Sink failures are
+    /// reported on stderr.
+    pub async fn deliver(&self, alert: Alert) {
+        eprintln!("{}", alert.message());
+
+        let alert = Arc::new(alert);
+        let mut tasks = tokio::task::JoinSet::new();
+        for sink in &self.sinks {
+            let sink = Arc::clone(sink);
+            let alert = Arc::clone(&alert);
+            tasks.spawn(async move {
+                if let Err(e) = sink.send(&alert).await {
+                    eprintln!("Alert sink {} failed: {}", sink.name(), e);
+                }
+            });
+        }
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// `follow` options choosing where alerts go.
+#[derive(Debug, Args)]
+pub struct AlertArgs {
+    /// Webhook URL to POST alerts to (same as `--sink webhook:<url>`)
+    #[arg(long)]
+    webhook: Option<String>,
+
+    /// Body format for `--webhook` and `webhook:` sinks: generic, slack, discord or matrix
+    #[arg(long, default_value = "generic")]
+    webhook_format: String,
+
+    /// Template file for `--webhook` and `webhook:` sinks, overriding `--webhook-format`.
+    /// `{{ name }}` inserts a value, `{{ name | json }}` a JSON-encoded one
+    #[arg(long)]
+    webhook_template: Option<String>,
+
+    /// Retries for a failed webhook request, with exponential backoff
+    #[arg(long, default_value_t = 4)]
+    webhook_retries: u32,
+
+    /// Delay before the first webhook retry; doubles on each further retry
+    #[arg(long, default_value = "2s")]
+    webhook_backoff: String,
+
+    /// Timeout for a single webhook, ntfy or Gotify request
+    #[arg(long, default_value = "10s")]
+    webhook_timeout: String,
+
+    /// Shared secret; webhook requests carry `X-Sentinel-Signature-256: sha256=<HMAC of body>`
+    #[arg(long)]
+    webhook_secret: Option<String>,
+
+    /// JSONL file for webhook alerts that failed every retry; replayed on startup
+    /// (permanently rejected ones go to <file>.rejected.jsonl)
+    /// [default: $XDG_STATE_HOME/sentinel/webhook-dead-letter.jsonl]
+    #[arg(long)]
+    dead_letter: Option<String>,
+
+    /// Extra alert destination; repeatable. `webhook:<url>`, `slack:<url>`, `discord:<url>`,
+    /// `matrix:<url>`, `ntfy:<url>[?token=..]`,
+    /// `gotify:<url>?token=..`, `exec:<command>`,
+    /// `email:smtp[s]://[user:pass@]host[:port]?from=..&to=..`, `nostr-dm:<npub>[,<npub>]`
+    #[arg(long)]
+    sink: Vec<String>,
+
+    /// Show alerts as desktop notifications (needs the `notify` feature)
+    #[arg(long)]
+    notify: bool,
+}
+
+impl AlertArgs {
+    /// Whether some sink signs its alerts with the identity (`nostr-dm`).
+    pub fn signs(&self) -> bool {
+        self.sink.iter().any(|spec| spec.starts_with("nostr-dm:"))
+    }
+
+    /// Build the alerter, replaying dead-lettered webhooks in the background.
+    pub async fn alerter(self, relays: &[String], keys: Option<&Keys>) -> Result<Alerter> {
+        let webhook_format = match self.webhook_template {
+            Some(path) => WebhookFormat::Template(Template::parse(&std::fs::read_to_string(path)?)?),
+            None => self.webhook_format.parse()?,
+        };
+        let delivery = Arc::new(Delivery::new(DeliveryOptions {
+            retries: self.webhook_retries,
+            backoff: parse_duration_str(&self.webhook_backoff)?,
+            timeout: parse_duration_str(&self.webhook_timeout)?,
+            secret: self.webhook_secret,
+            dead_letter: self.dead_letter.map(PathBuf::from).or_else(default_dead_letter_path),
+        }));
+        let replay = Arc::clone(&delivery);
+        tokio::spawn(async move {
+            match replay.replay().await {
+                Ok((0, 0)) => {}
+                Ok((delivered, remaining)) => eprintln!(
+                    "Replayed dead-letter webhooks: {} delivered, {} still failing",
+                    delivered, remaining
+                ),
+                Err(e) => eprintln!("Could not replay dead-letter webhooks: {}", e),
+            }
+        });
+
+        let mut specs: Vec<String> = self.webhook.map(|url| format!("webhook:{}", url)).into_iter().collect();
+        specs.extend(self.sink);
+        let ctx = SinkContext {
+            relays,
+            keys,
+            webhook_format: &webhook_format,
+            delivery: &delivery,
+        };
+        let mut alerter = Alerter::from_specs(&specs, &ctx).await?;
+        if self.notify {
+            alerter.add(desktop::connect().await?);
+        }
+        Ok(alerter)
+    }
+}
+
+/// `$XDG_STATE_HOME/sentinel/webhook-dead-letter.jsonl`, falling back to
+/// `~/.local/state`.
+fn default_dead_letter_path() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))?;
+    Some(state.join("sentinel").join("webhook-dead-letter.jsonl"))
+}
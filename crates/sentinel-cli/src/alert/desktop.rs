@@ -0,0 +1,271 @@
+//! Desktop notifications over the freedesktop D-Bus notification spec. The
+//! D-Bus client is behind the `notify` feature; grouping and map actions are
+//! plain logic over [`NotificationServer`].
+#![cfg_attr(not(feature = "notify"), allow(dead_code))]
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{map_url, Alert, AlertSink, SinkError};
+
+const OPEN_MAP: &str = "open-map";
+
+/// A notification as passed to `org.freedesktop.Notifications.Notify`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    /// Id of the notification to replace, 0 for a new one.
+    pub replaces_id: u32,
+    pub summary: String,
+    pub body: String,
+    /// `(key, label)` pairs.
+    pub actions: Vec<(String, String)>,
+    /// 0 low, 1 normal, 2 critical.
+    pub urgency: u8,
+}
+
+/// The notification server, i.e. the session bus in production.
+#[async_trait]
+pub trait NotificationServer: Send + Sync {
+    /// Show `notification`, returning its id.
+    async fn notify(&self, notification: &Notification) -> Result<u32, SinkError>;
+}
+
+#[derive(Default)]
+struct Groups {
+    /// Latest notification id per followed pubkey (or `relays`).
+    ids: HashMap<String, u32>,
+    /// Map link per notification id.
+    urls: HashMap<u32, String>,
+}
+
+/// Shows alerts as desktop notifications, one per followed pubkey: a new
+/// alert replaces the previous notification for the same pubkey.
+pub struct Desktop<S> {
+    server: S,
+    groups: Mutex<Groups>,
+}
+
+impl<S: NotificationServer> Desktop<S> {
+    pub fn new(server: S) -> Self {
+        Self { server, groups: Mutex::new(Groups::default()) }
+    }
+
+    /// Map URL for an invoked action, if the notification has one.
+    pub fn action_url(&self, id: u32, action: &str) -> Option<String> {
+        match action {
+            "default" | OPEN_MAP => self.groups.lock().unwrap().urls.get(&id).cloned(),
+            _ => None,
+        }
+    }
+}
+
+fn group_key(alert: &Alert) -> String {
+    match alert.target() {
+        Some(target) => target.pubkey.to_hex(),
+        None => "relays".to_string(),
+    }
+}
+
+#[async_trait]
+impl<S: NotificationServer> AlertSink for Desktop<S> {
+    fn name(&self) -> String {
+        "desktop notifications".to_string()
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        let key = group_key(alert);
+        let url = alert.location().map(map_url);
+        let notification = Notification {
+            replaces_id: self.groups.lock().unwrap().ids.get(&key).copied().unwrap_or(0),
+            summary: alert.title(),
+            body: alert.message(),
+            actions: match url {
+                Some(_) => vec![
+                    ("default".to_string(), "Open map".to_string()),
+                    (OPEN_MAP.to_string(), "Open map".to_string()),
+                ],
+                None => Vec::new(),
+            },
+            urgency: match alert {
+                Alert::Silence { degraded: false, .. } => 2,
+                _ if alert.is_problem() => 1,
+                _ => 0,
+            },
+        };
+
+        let id = self.server.notify(&notification).await?;
+
+        let mut groups = self.groups.lock().unwrap();
+        if let Some(old) = groups.ids.insert(key, id) {
+            groups.urls.remove(&old);
+        }
+        match url {
+            Some(url) => groups.urls.insert(id, url),
+            None => groups.urls.remove(&id),
+        };
+        Ok(())
+    }
+}
+
+/// Connect to the session bus. Invoked map actions open with `xdg-open`.
+#[cfg(feature = "notify")]
+pub async fn connect() -> Result<Arc<dyn AlertSink>, SinkError> {
+    dbus::connect().await
+}
+
+#[cfg(not(feature = "notify"))]
+pub async fn connect() -> Result<Arc<dyn AlertSink>, SinkError> {
+    Err(SinkError::Config(
+        "desktop notifications need sentinel built with `--features notify`".into(),
+    ))
+}
+
+#[cfg(feature = "notify")]
+mod dbus {
+    use futures_util::StreamExt;
+    use std::collections::HashMap;
+    use zbus::zvariant::Value;
+
+    use super::*;
+
+    #[zbus::proxy(
+        interface = "org.freedesktop.Notifications",
+        default_service = "org.freedesktop.Notifications",
+        default_path = "/org/freedesktop/Notifications"
+    )]
+    trait Notifications {
+        #[allow(clippy::too_many_arguments)]
+        fn notify(
+            &self,
+            app_name: &str,
+            replaces_id: u32,
+            app_icon: &str,
+            summary: &str,
+            body: &str,
+            actions: &[&str],
+            hints: HashMap<&str, Value<'_>>,
+            expire_timeout: i32,
+        ) -> zbus::Result<u32>;
+
+        #[zbus(signal)]
+        fn action_invoked(&self, id: u32, action_key: &str) -> zbus::Result<()>;
+    }
+
+    struct SessionBus(NotificationsProxy<'static>);
+
+    #[async_trait]
+    impl NotificationServer for SessionBus {
+        async fn notify(&self, n: &Notification) -> Result<u32, SinkError> {
+            let actions: Vec<&str> = n
+                .actions
+                .iter()
+                .flat_map(|(key, label)| [key.as_str(), label.as_str()])
+                .collect();
+            let hints = HashMap::from([
+                ("urgency", Value::from(n.urgency)),
+                ("category", Value::from("presence")),
+            ]);
+            self.0
+                .notify("Sentinel", n.replaces_id, "find-location", &n.summary, &n.body, &actions, hints, -1)
+                .await
+                .map_err(|e| SinkError::Config(format!("D-Bus: {}", e)))
+        }
+    }
+
+    pub async fn connect() -> Result<Arc<dyn AlertSink>, SinkError> {
+        let dbus_err = |e: zbus::Error| SinkError::Config(format!("D-Bus: {}", e));
+        let connection = zbus::Connection::session().await.map_err(dbus_err)?;
+        let proxy = NotificationsProxy::new(&connection).await.map_err(dbus_err)?;
+        let mut actions = proxy.receive_action_invoked().await.map_err(dbus_err)?;
+
+        let desktop = Arc::new(Desktop::new(SessionBus(proxy)));
+        let listener = Arc::clone(&desktop);
+        tokio::spawn(async move {
+            while let Some(signal) = actions.next().await {
+                let Ok(args) = signal.args() else { continue };
+                if let Some(url) = listener.action_url(args.id, args.action_key) {
+                    let _ = tokio::process::Command::new("xdg-open").arg(url).spawn();
+                }
+            }
+        });
+        Ok(desktop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follow::Target;
+    use nostr_sdk::prelude::*;
+    use sentinel_core::event_parser::ParsedLocation;
+
+    /// Session-bus stand-in: records notifications and hands out ids.
+    #[derive(Default)]
+    struct StandIn {
+        sent: Mutex<Vec<Notification>>,
+    }
+
+    #[async_trait]
+    impl NotificationServer for StandIn {
+        async fn notify(&self, notification: &Notification) -> Result<u32, SinkError> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push(notification.clone());
+            Ok(match notification.replaces_id {
+                0 => sent.len() as u32,
+                id => id,
+            })
+        }
+    }
+
+    fn location() -> ParsedLocation {
+        ParsedLocation {
+            geohash: "ud9wr".into(),
+            lat: 60.17,
+            lon: 24.94,
+            accuracy: Some(10.0),
+            d_tag: "phone".into(),
+            timestamp: Timestamp::from(1_000_000),
+            kind: 30472,
+            pubkey: String::new(),
+            speed: None,
+            course: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn notifications_grouped_per_pubkey_with_map_action() {
+        let desktop = Desktop::new(StandIn::default());
+        let alice = Target::parse(&format!("Alice={}@10m", Keys::generate().public_key()), None).unwrap();
+        let bob = Target::parse(&format!("Bob={}@10m", Keys::generate().public_key()), None).unwrap();
+        let silence = |target: &Target, location| Alert::Silence {
+            target: target.clone(),
+            last_seen: None,
+            location,
+            silent_secs: 600,
+            repeat: 1,
+            degraded: false,
+        };
+
+        desktop.send(&silence(&alice, Some(location()))).await.unwrap();
+        desktop.send(&silence(&bob, None)).await.unwrap();
+        desktop.send(&silence(&alice, None)).await.unwrap();
+
+        let sent = desktop.server.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 3);
+        assert_eq!(sent[0].replaces_id, 0);
+        assert_eq!(sent[0].summary, "Alice silent for 10m");
+        assert_eq!(sent[0].urgency, 2);
+        assert_eq!(sent[0].actions[1].0, OPEN_MAP);
+        assert_eq!(sent[1].replaces_id, 0);
+        assert!(sent[1].actions.is_empty());
+        // Alice's second alert replaces her first notification.
+        assert_eq!(sent[2].replaces_id, 1);
+
+        // The replacement has no location, so its action is gone too.
+        assert_eq!(desktop.action_url(1, OPEN_MAP), None);
+        desktop.send(&silence(&alice, Some(location()))).await.unwrap();
+        assert_eq!(desktop.action_url(1, OPEN_MAP), Some(map_url(&location())));
+        assert_eq!(desktop.action_url(1, "dismiss"), None);
+    }
+}
@@ -0,0 +1,90 @@
+use async_trait::async_trait;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::{Alert, AlertSink, SinkError};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs a shell command per alert. The alert is passed as
+/// `SENTINEL_ALERT_TYPE`, `SENTINEL_ALERT_TITLE`, `SENTINEL_ALERT_TEXT` and
+/// `SENTINEL_ALERT_JSON`, and as JSON on stdin.
+pub struct Exec {
+    command: String,
+}
+
+impl Exec {
+    pub fn new(command: &str) -> Self {
+        Self { command: command.to_string() }
+    }
+}
+
+#[async_trait]
+impl AlertSink for Exec {
+    fn name(&self) -> String {
+        format!("exec '{}'", self.command)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        let json = alert.to_json().to_string();
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .env("SENTINEL_ALERT_TYPE", alert.kind())
+            .env("SENTINEL_ALERT_TITLE", alert.title())
+            .env("SENTINEL_ALERT_TEXT", alert.message())
+            .env("SENTINEL_ALERT_JSON", &json)
+            .stdin(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The command may not read stdin at all.
+            let _ = stdin.write_all(json.as_bytes()).await;
+        }
+        let status = tokio::time::timeout(TIMEOUT, child.wait())
+            .await
+            .map_err(|_| SinkError::Exec(format!("timed out after {}s", TIMEOUT.as_secs())))??;
+        if !status.success() {
+            return Err(SinkError::Exec(status.to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn exec_passes_alert_in_env_and_stdin() {
+        let dir = std::env::temp_dir().join(format!("sentinel-exec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("alert");
+        let command = format!(
+            "cat > {0}.json && printf '%s|%s' \"$SENTINEL_ALERT_TYPE\" \"$SENTINEL_ALERT_TITLE\" > {0}.env",
+            out.display()
+        );
+
+        let alert = Alert::RelayDown {
+            relay: "wss://relay.example.com".into(),
+            since: nostr_sdk::Timestamp::from(1_000_000),
+        };
+        Exec::new(&command).send(&alert).await.unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(out.with_extension("json")).unwrap()).unwrap();
+        assert_eq!(json["type"], "relay_down");
+        assert_eq!(json["relay"], "wss://relay.example.com");
+        assert_eq!(
+            std::fs::read_to_string(out.with_extension("env")).unwrap(),
+            "relay_down|Relay wss://relay.example.com unreachable"
+        );
+
+        let failing = Exec::new("exit 3").send(&alert).await;
+        assert!(matches!(failing, Err(SinkError::Exec(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+//! Webhook payload formats: presets for common chat services and
+//! user-supplied templates.
+
+use nostr_sdk::prelude::*;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use super::{map_url, Alert, SinkError};
+use crate::follow::format_secs;
+
+/// Variables available to templates.
+pub const VARIABLES: &[&str] = &[
+    "type",
+    "title",
+    "text",
+    "name",
+    "pubkey",
+    "npub",
+    "d_tag",
+    "lat",
+    "lon",
+    "geohash",
+    "accuracy",
+    "timestamp",
+    "map_url",
+    "last_seen",
+    "silent_secs",
+    "silence",
+    "repeat",
+    "degraded",
+    "zone",
+    "transition",
+    "relay",
+];
+
+/// A rendered webhook body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// How a webhook body is built from an alert.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookFormat {
+    /// [`Alert::to_json`].
+    Generic,
+    /// Slack incoming webhook (`text` with mrkdwn).
+    Slack,
+    /// Discord webhook with an embed.
+    Discord,
+    /// Matrix hookshot generic webhook (`text` and `html`).
+    Matrix,
+    Template(Template),
+}
+
+impl FromStr for WebhookFormat {
+    type Err = SinkError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" | "json" => Ok(Self::Generic),
+            "slack" => Ok(Self::Slack),
+            "discord" => Ok(Self::Discord),
+            "matrix" => Ok(Self::Matrix),
+            _ => Err(SinkError::Config(format!(
+                "unknown webhook format '{}' (generic, slack, discord, matrix)",
+                s
+            ))),
+        }
+    }
+}
+
+impl WebhookFormat {
+    pub fn render(&self, alert: &Alert) -> Payload {
+        let body = match self {
+            Self::Generic => alert.to_json(),
+            Self::Slack => {
+                let mut text = format!("*{}*\n{}", alert.title(), alert.message());
+                if let Some(url) = alert.location().map(map_url) {
+                    text.push_str(&format!("\n<{}|Open map>", url));
+                }
+                json!({ "text": text })
+            }
+            Self::Discord => {
+                let color = match alert {
+                    Alert::Silence { degraded: true, .. } => 0xf1c40f,
+                    _ if alert.is_problem() => 0xe74c3c,
+                    Alert::Geofence { .. } => 0x3498db,
+                    _ => 0x2ecc71,
+                };
+                let mut embed = json!({
+                    "title": alert.title(),
+                    "description": alert.message(),
+                    "color": color,
+                });
+                if let Some(url) = alert.location().map(map_url) {
+                    embed["url"] = url.into();
+                }
+                json!({ "username": "Sentinel", "embeds": [embed] })
+            }
+            Self::Matrix => {
+                let mut html = format!(
+                    "<strong>{}</strong><br>{}",
+                    escape_html(&alert.title()),
+                    escape_html(&alert.message())
+                );
+                if let Some(url) = alert.location().map(map_url) {
+                    html.push_str(&format!(" — <a href=\"{}\">map</a>", escape_html(&url)));
+                }
+                json!({ "text": alert.message(), "html": html, "username": "Sentinel" })
+            }
+            Self::Template(template) => return template.render(alert),
+        };
+        Payload { content_type: "application/json", body: body.to_string() }
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Template values for `alert`. Values that don't apply are `null`.
+pub fn variables(alert: &Alert) -> BTreeMap<&'static str, Value> {
+    let mut vars: BTreeMap<&'static str, Value> =
+        VARIABLES.iter().map(|name| (*name, Value::Null)).collect();
+    vars.insert("type", alert.kind().into());
+    vars.insert("title", alert.title().into());
+    vars.insert("text", alert.message().into());
+
+    if let Some(target) = alert.target() {
+        vars.insert("name", target.name.clone().into());
+        vars.insert("pubkey", target.pubkey.to_hex().into());
+        vars.insert("npub", target.pubkey.to_bech32().unwrap_or_default().into());
+        vars.insert("d_tag", target.d_tag.clone().into());
+    }
+    if let Some(loc) = alert.location() {
+        vars.insert("lat", loc.lat.into());
+        vars.insert("lon", loc.lon.into());
+        vars.insert("geohash", loc.geohash.clone().into());
+        vars.insert("accuracy", loc.accuracy.into());
+        vars.insert("timestamp", loc.timestamp.as_u64().into());
+        vars.insert("map_url", map_url(loc).into());
+        // A location narrows a pubkey-wide target to its device.
+        if vars["d_tag"].is_null() {
+            vars.insert("d_tag", loc.d_tag.clone().into());
+        }
+    }
+    match alert {
+        Alert::Silence { last_seen, silent_secs, repeat, degraded, .. } => {
+            vars.insert("last_seen", last_seen.map(|ts| ts.to_human_datetime()).into());
+            vars.insert("silent_secs", (*silent_secs).into());
+            vars.insert("silence", format_secs(*silent_secs).into());
+            vars.insert("repeat", (*repeat).into());
+            vars.insert("degraded", (*degraded).into());
+        }
+        Alert::Recovered { silent_secs, .. } => {
+            vars.insert("silent_secs", (*silent_secs).into());
+            vars.insert("silence", format_secs(*silent_secs).into());
+        }
+        Alert::Geofence { transition, .. } => {
+            vars.insert("zone", transition.zone.clone().into());
+            vars.insert("transition", serde_json::to_value(transition.kind).unwrap_or_default());
+        }
+        Alert::RelayDown { relay, .. } | Alert::RelayUp { relay, .. } => {
+            vars.insert("relay", relay.clone().into());
+        }
+    }
+    vars
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    /// `{{ name }}` inserts the plain value, `{{ name | json }}` a JSON value.
+    /// `in_string`: a plain value inside a string literal of a JSON template,
+    /// escaped as string content.
+    Var { name: String, json: bool, in_string: bool },
+}
+
+/// A `{{ variable }}` template. Plain values render `null` as empty, while
+/// `{{ variable | json }}` renders a quoted, escaped JSON value for use inside
+/// JSON bodies. In a template that starts with `{` or `[`, plain values
+/// inside `"..."` are JSON-escaped, so quotes in names keep the body valid.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Parse a template, rejecting unknown variables and filters.
+    pub fn parse(source: &str) -> Result<Self, SinkError> {
+        let json_template = source.trim_start().starts_with(['{', '[']);
+        // Whether the text so far ends inside a JSON string literal.
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                for c in rest[..start].chars() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' if in_string => escaped = true,
+                        '"' => in_string = !in_string,
+                        _ => {}
+                    }
+                }
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let after = &rest[start + 2..];
+            let end = after
+                .find("}}")
+                .ok_or_else(|| SinkError::Config("template: unclosed '{{'".into()))?;
+            let expr = after[..end].trim();
+            let (name, filter) = match expr.split_once('|') {
+                Some((name, filter)) => (name.trim(), Some(filter.trim())),
+                None => (expr, None),
+            };
+            if !VARIABLES.contains(&name) {
+                return Err(SinkError::Config(format!(
+                    "template: unknown variable '{}' (available: {})",
+                    name,
+                    VARIABLES.join(", ")
+                )));
+            }
+            let json = match filter {
+                None => false,
+                Some("json") => true,
+                Some(other) => {
+                    return Err(SinkError::Config(format!("template: unknown filter '{}'", other)))
+                }
+            };
+            parts.push(Part::Var { name: name.to_string(), json, in_string: json_template && in_string });
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Render for `alert`. Output that parses as JSON is sent as JSON, anything
+    /// else as plain text.
+    pub fn render(&self, alert: &Alert) -> Payload {
+        let vars = variables(alert);
+        let mut body = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(text) => body.push_str(text),
+                Part::Var { name, json: true, .. } => body.push_str(&vars[name.as_str()].to_string()),
+                Part::Var { name, json: false, in_string } => match &vars[name.as_str()] {
+                    Value::Null => {}
+                    Value::String(s) if *in_string => {
+                        let quoted = Value::from(s.as_str()).to_string();
+                        body.push_str(&quoted[1..quoted.len() - 1]);
+                    }
+                    Value::String(s) => body.push_str(s),
+                    other => body.push_str(&other.to_string()),
+                },
+            }
+        }
+        let content_type = match serde_json::from_str::<Value>(&body) {
+            Ok(_) => "application/json",
+            Err(_) => "text/plain; charset=utf-8",
+        };
+        Payload { content_type, body }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follow::Target;
+    use sentinel_core::event_parser::ParsedLocation;
+
+    fn silence() -> Alert {
+        silence_of("Alice")
+    }
+
+    fn silence_of(name: &str) -> Alert {
+        let keys = Keys::generate();
+        Alert::Silence {
+            target: Target::parse(&format!("{}={}@10m", name, keys.public_key()), None).unwrap(),
+            last_seen: Some(Timestamp::from(1_000_000)),
+            location: Some(ParsedLocation {
+                geohash: "ud9wr".into(),
+                lat: 60.17,
+                lon: 24.94,
+                accuracy: None,
+                d_tag: "phone".into(),
+                timestamp: Timestamp::from(1_000_000),
+                kind: 30472,
+                pubkey: keys.public_key().to_hex(),
+                speed: None,
+                course: None,
+            }),
+            silent_secs: 5400,
+            repeat: 1,
+            degraded: false,
+        }
+    }
+
+    #[test]
+    fn template_renders_variables() {
+        let template = Template::parse(
+            r#"{"summary": {{ title | json }}, "who": "{{name}}/{{d_tag}}", "lat": {{lat}}, "acc": {{accuracy|json}}, "silent": "{{silence}}"}"#,
+        )
+        .unwrap();
+        let payload = template.render(&silence());
+        assert_eq!(payload.content_type, "application/json");
+        let body: Value = serde_json::from_str(&payload.body).unwrap();
+        assert_eq!(body["summary"], "Alice silent for 1h30m");
+        assert_eq!(body["who"], "Alice/phone");
+        assert_eq!(body["lat"], 60.17);
+        assert_eq!(body["acc"], Value::Null);
+        assert_eq!(body["silent"], "1h30m");
+
+        let text = Template::parse("{{type}} {{relay}}at {{map_url}}").unwrap().render(&silence());
+        assert_eq!(text.content_type, "text/plain; charset=utf-8");
+        assert!(text.body.starts_with("silence at https://www.openstreetmap.org/?mlat=60.170000"));
+
+        // Plain values inside JSON strings are escaped; elsewhere they are not.
+        let quoted = template.render(&silence_of(r#"Al "The Van" \ 2"#));
+        assert_eq!(quoted.content_type, "application/json");
+        let body: Value = serde_json::from_str(&quoted.body).unwrap();
+        assert_eq!(body["who"], r#"Al "The Van" \ 2/phone"#);
+        let text = Template::parse("who: \"{{name}}\"").unwrap().render(&silence_of(r#"A"B"#));
+        assert_eq!(text.body, r#"who: "A"B""#);
+        let escaped = Template::parse(r#"{"a": "\"", "b": "{{name}}"}"#).unwrap().render(&silence_of(r#"A"B"#));
+        assert_eq!(escaped.body, r#"{"a": "\"", "b": "A\"B"}"#);
+    }
+
+    #[test]
+    fn template_rejects_unknown_names() {
+        assert!(Template::parse("{{ latitude }}").is_err());
+        assert!(Template::parse("{{ lat | upper }}").is_err());
+        assert!(Template::parse("{{ lat ").is_err());
+    }
+
+    #[test]
+    fn presets() {
+        let alert = silence();
+        let discord: Value =
+            serde_json::from_str(&WebhookFormat::Discord.render(&alert).body).unwrap();
+        assert_eq!(discord["embeds"][0]["title"], "Alice silent for 1h30m");
+        assert!(discord["embeds"][0]["url"].as_str().unwrap().contains("mlat=60.170000"));
+
+        let slack: Value = serde_json::from_str(&WebhookFormat::Slack.render(&alert).body).unwrap();
+        assert!(slack["text"].as_str().unwrap().ends_with("|Open map>"));
+
+        let matrix: Value = serde_json::from_str(&WebhookFormat::Matrix.render(&alert).body).unwrap();
+        assert!(matrix["html"].as_str().unwrap().starts_with("<strong>Alice silent"));
+
+        assert_eq!("discord".parse::<WebhookFormat>().unwrap(), WebhookFormat::Discord);
+        assert!("teams".parse::<WebhookFormat>().is_err());
+    }
+}
@@ -0,0 +1,541 @@
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use nostr_sdk::nostr::util::hex;
+use nostr_sdk::Timestamp;
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use super::format::Payload;
+use super::{Alert, AlertSink, SinkError, WebhookFormat};
+
+/// Header carrying `sha256=<hex HMAC of the body>` when a secret is set.
+pub const SIGNATURE_HEADER: &str = "X-Sentinel-Signature-256";
+
+fn client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
+
+/// Parse `url`, removing and returning its `token` query parameter.
+fn split_token(url: &str) -> Result<(Url, Option<String>), SinkError> {
+    let mut url = Url::parse(url).map_err(|e| SinkError::Config(format!("{}: {}", url, e)))?;
+    let mut token = None;
+    let rest: Vec<(String, String)> = url
+        .query_pairs()
+        .filter_map(|(k, v)| match k.as_ref() {
+            "token" => {
+                token = Some(v.into_owned());
+                None
+            }
+            _ => Some((k.into_owned(), v.into_owned())),
+        })
+        .collect();
+    if rest.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(rest);
+    }
+    Ok((url, token))
+}
+
+/// Whether a failed request may succeed later: network errors, server
+/// errors, 408 and 429. Other client errors are permanent.
+fn retryable(error: &SinkError) -> bool {
+    match error {
+        SinkError::Status(status) => {
+            status.is_server_error()
+                || *status == StatusCode::TOO_MANY_REQUESTS
+                || *status == StatusCode::REQUEST_TIMEOUT
+        }
+        _ => true,
+    }
+}
+
+fn check_status(response: reqwest::Response) -> Result<(), SinkError> {
+    match response.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(SinkError::Status(status)),
+    }
+}
+
+/// Retry, signing and dead-letter settings shared by all webhook sinks.
+#[derive(Debug, Clone)]
+pub struct DeliveryOptions {
+    /// Attempts after the first one.
+    pub retries: u32,
+    /// Delay before the first retry; doubles on each further retry.
+    pub backoff: Duration,
+    /// Per-request timeout.
+    pub timeout: Duration,
+    /// HMAC-SHA256 key for [`SIGNATURE_HEADER`].
+    pub secret: Option<String>,
+    /// JSONL file for alerts that still failed after all retries. Requests
+    /// the endpoint rejected for good go to [`rejected_path`] instead.
+    pub dead_letter: Option<PathBuf>,
+}
+
+/// An undelivered webhook request, one JSON object per line in the
+/// dead-letter file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    pub content_type: String,
+    pub body: String,
+    pub failed_at: u64,
+    pub attempts: u32,
+    pub error: String,
+}
+
+/// Delivers webhook requests with retries, signatures and a dead-letter log.
+pub struct Delivery {
+    options: DeliveryOptions,
+    client: reqwest::Client,
+    /// Serializes access to the dead-letter file.
+    dead_letter_lock: tokio::sync::Mutex<()>,
+}
+
+impl Delivery {
+    pub fn new(options: DeliveryOptions) -> Self {
+        let client = client(options.timeout);
+        Self { options, client, dead_letter_lock: tokio::sync::Mutex::new(()) }
+    }
+
+    /// The per-request timeout, also used by the ntfy and Gotify sinks.
+    pub fn timeout(&self) -> Duration {
+        self.options.timeout
+    }
+
+    fn sign(&self, body: &str) -> Option<String> {
+        let secret = self.options.secret.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+        mac.update(body.as_bytes());
+        Some(format!("sha256={}", hex::encode(mac.finalize().into_bytes())))
+    }
+
+    async fn attempt(&self, url: &str, content_type: &str, body: &str) -> Result<(), SinkError> {
+        let mut request = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body.to_string());
+        if let Some(signature) = self.sign(body) {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        check_status(request.send().await?)
+    }
+
+    /// POST with exponential backoff. Client errors other than 408/429 are not
+    /// retried. Returns the number of attempts made alongside any final error.
+    async fn post(&self, url: &str, content_type: &str, body: &str) -> (u32, Result<(), SinkError>) {
+        let mut delay = self.options.backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let result = self.attempt(url, content_type, body).await;
+            let retry = result.as_ref().is_err_and(retryable);
+            if !retry || attempts > self.options.retries {
+                return (attempts, result);
+            }
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(2);
+        }
+    }
+
+    /// Deliver, writing the request to the dead-letter file if it still fails.
+    pub async fn deliver(&self, url: &Url, payload: &Payload) -> Result<(), SinkError> {
+        let (attempts, result) = self.post(url.as_str(), payload.content_type, &payload.body).await;
+        if let (Err(e), Some(path)) = (&result, &self.options.dead_letter) {
+            let entry = DeadLetter {
+                url: url.to_string(),
+                content_type: payload.content_type.to_string(),
+                body: payload.body.clone(),
+                failed_at: Timestamp::now().as_u64(),
+                attempts,
+                error: e.to_string(),
+            };
+            let path = if retryable(e) { path.clone() } else { rejected_path(path) };
+            let _guard = self.dead_letter_lock.lock().await;
+            if let Err(write_err) = append_dead_letters(&path, &[entry]).await {
+                eprintln!("Could not write dead-letter file {}: {}", path.display(), write_err);
+            }
+        }
+        result
+    }
+
+    /// Try every request in the dead-letter file once, keeping the ones that
+    /// may still succeed and moving permanently rejected ones to
+    /// [`rejected_path`]. Returns `(delivered, remaining)`.
+    ///
+    /// The file is only locked while it is read and rewritten, so alerts
+    /// failing meanwhile are appended and kept.
+    pub async fn replay(&self) -> Result<(usize, usize), SinkError> {
+        let Some(path) = &self.options.dead_letter else {
+            return Ok((0, 0));
+        };
+        let content = {
+            let _guard = self.dead_letter_lock.lock().await;
+            match tokio::fs::read_to_string(path).await {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((0, 0)),
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let mut remaining = Vec::new();
+        let mut rejected = Vec::new();
+        let mut delivered = 0;
+        for line in content.lines().filter(|l| !l.trim().is_empty()) {
+            let mut entry: DeadLetter = match serde_json::from_str(line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    eprintln!("Dropping unreadable dead-letter entry: {}", e);
+                    continue;
+                }
+            };
+            match self.attempt(&entry.url, &entry.content_type, &entry.body).await {
+                Ok(()) => delivered += 1,
+                Err(e) => {
+                    entry.attempts += 1;
+                    entry.error = e.to_string();
+                    if retryable(&e) {
+                        remaining.push(entry);
+                    } else {
+                        rejected.push(entry);
+                    }
+                }
+            }
+        }
+
+        let _guard = self.dead_letter_lock.lock().await;
+        if !rejected.is_empty() {
+            append_dead_letters(&rejected_path(path), &rejected).await?;
+        }
+        // Entries that failed during the replay were appended after `content`.
+        let now = tokio::fs::read_to_string(path).await.unwrap_or_default();
+        for line in now.get(content.len()..).unwrap_or_default().lines() {
+            if let Ok(entry) = serde_json::from_str(line) {
+                remaining.push(entry);
+            }
+        }
+        // Rewrite through a temporary file so a crash can't lose entries.
+        let tmp = path.with_extension("tmp");
+        let _ = tokio::fs::remove_file(&tmp).await;
+        append_dead_letters(&tmp, &remaining).await?;
+        tokio::fs::rename(&tmp, path).await?;
+        Ok((delivered, remaining.len()))
+    }
+}
+
+/// Where requests rejected with a permanent client error are kept:
+/// `<dead-letter>.rejected.jsonl`. They are never replayed.
+fn rejected_path(dead_letter: &Path) -> PathBuf {
+    dead_letter.with_extension("rejected.jsonl")
+}
+
+async fn append_dead_letters(path: &Path, entries: &[DeadLetter]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut lines = String::new();
+    for entry in entries {
+        lines.push_str(&serde_json::to_string(entry)?);
+        lines.push('\n');
+    }
+    // Entries hold alert payloads with locations: owner only.
+    let mut options = tokio::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path).await?;
+    file.write_all(lines.as_bytes()).await?;
+    file.flush().await
+}
+
+/// POSTs the alert to a URL in the given format.
+pub struct Webhook {
+    url: Url,
+    format: WebhookFormat,
+    delivery: Arc<Delivery>,
+}
+
+impl Webhook {
+    pub fn new(url: &str, format: WebhookFormat, delivery: Arc<Delivery>) -> Result<Self, SinkError> {
+        let url = Url::parse(url).map_err(|e| SinkError::Config(format!("{}: {}", url, e)))?;
+        Ok(Self { url, format, delivery })
+    }
+}
+
+#[async_trait]
+impl AlertSink for Webhook {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        self.delivery.deliver(&self.url, &self.format.render(alert)).await
+    }
+}
+
+/// Publishes to an ntfy topic.
+pub struct Ntfy {
+    url: Url,
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl Ntfy {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, SinkError> {
+        let (url, token) = split_token(url)?;
+        Ok(Self { url, token, client: client(timeout) })
+    }
+}
+
+#[async_trait]
+impl AlertSink for Ntfy {
+    fn name(&self) -> String {
+        format!("ntfy {}", self.url)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        let (priority, tags) = match alert {
+            Alert::Silence { degraded: false, .. } => ("high", "warning"),
+            Alert::RelayDown { .. } | Alert::Silence { .. } => ("default", "satellite"),
+            Alert::Geofence { .. } => ("default", "round_pushpin"),
+            Alert::Recovered { .. } | Alert::RelayUp { .. } => ("low", "white_check_mark"),
+        };
+        let mut request = self
+            .client
+            .post(self.url.clone())
+            .header("Title", alert.title())
+            .header("Priority", priority)
+            .header("Tags", tags)
+            .body(alert.message());
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+        check_status(request.send().await?)
+    }
+}
+
+/// Pushes a message to a Gotify server.
+pub struct Gotify {
+    url: Url,
+    token: String,
+    client: reqwest::Client,
+}
+
+impl Gotify {
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, SinkError> {
+        let (url, token) = split_token(url)?;
+        let token = token.ok_or_else(|| SinkError::Config("gotify needs ?token=<app-token>".into()))?;
+        let url = url
+            .join("message")
+            .map_err(|e| SinkError::Config(format!("{}: {}", url, e)))?;
+        Ok(Self { url, token, client: client(timeout) })
+    }
+}
+
+#[async_trait]
+impl AlertSink for Gotify {
+    fn name(&self) -> String {
+        format!("gotify {}", self.url)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        let priority = if alert.is_problem() { 8 } else { 4 };
+        let response = self
+            .client
+            .post(self.url.clone())
+            .header("X-Gotify-Key", &self.token)
+            .json(&serde_json::json!({
+                "title": alert.title(),
+                "message": alert.message(),
+                "priority": priority,
+                "extras": { "sentinel::alert": alert.to_json() },
+            }))
+            .send()
+            .await?;
+        check_status(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::follow::Target;
+    use nostr_sdk::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Local HTTP server answering one request per status in `statuses`;
+    /// returns the raw requests.
+    async fn serve(listener: TcpListener, statuses: Vec<u16>) -> Vec<String> {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|l| {
+                            let l = l.to_ascii_lowercase();
+                            l.strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            let response =
+                format!("HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            requests.push(String::from_utf8(request).unwrap());
+        }
+        requests
+    }
+
+    async fn local_server(statuses: Vec<u16>) -> (Url, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/hook", listener.local_addr().unwrap())).unwrap();
+        (url, tokio::spawn(serve(listener, statuses)))
+    }
+
+    fn delivery(dead_letter: Option<PathBuf>) -> Delivery {
+        Delivery::new(DeliveryOptions {
+            retries: 2,
+            backoff: Duration::from_millis(10),
+            timeout: Duration::from_secs(5),
+            secret: Some("s3cret".into()),
+            dead_letter,
+        })
+    }
+
+    fn payload() -> Payload {
+        Payload { content_type: "application/json", body: r#"{"text":"hi"}"#.into() }
+    }
+
+    #[tokio::test]
+    async fn webhook_retries_and_signs() {
+        let (url, server) = local_server(vec![503, 500, 200]).await;
+        delivery(None).deliver(&url, &payload()).await.unwrap();
+
+        let requests = server.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        // echo -n '{"text":"hi"}' | openssl dgst -sha256 -hmac s3cret
+        let header = format!(
+            "{}: sha256=033b6215e0418d45ead2c73e9d3059b3efba91da4d5f556dc6aa782294cff195",
+            SIGNATURE_HEADER.to_ascii_lowercase()
+        );
+        assert!(requests.iter().all(|r| r.to_ascii_lowercase().contains(&header)));
+    }
+
+    #[tokio::test]
+    async fn failed_webhooks_are_dead_lettered_and_replayed() {
+        let dir = std::env::temp_dir().join(format!("sentinel-dead-letter-{}", std::process::id()));
+        let path = dir.join("dead-letter.jsonl");
+        let _ = std::fs::remove_dir_all(&dir);
+        let read = |path: &Path| -> Vec<DeadLetter> {
+            std::fs::read_to_string(path).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+        };
+
+        // Client errors are not retried, and kept apart from the dead letters.
+        let (url, server) = local_server(vec![400]).await;
+        assert!(delivery(Some(path.clone())).deliver(&url, &payload()).await.is_err());
+        assert_eq!(server.await.unwrap().len(), 1);
+        assert_eq!(read(&rejected_path(&path)).len(), 1);
+
+        let (first, server) = local_server(vec![500, 500, 500]).await;
+        assert!(delivery(Some(path.clone())).deliver(&first, &payload()).await.is_err());
+        server.await.unwrap();
+        let (second, server) = local_server(vec![502, 502, 502]).await;
+        assert!(delivery(Some(path.clone())).deliver(&second, &payload()).await.is_err());
+        server.await.unwrap();
+
+        let entries = read(&path);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].attempts, 3);
+        assert_eq!(entries[0].url, first.to_string());
+        assert_eq!(entries[0].body, payload().body);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // On restart the first endpoint is back and the second rejects the
+        // request for good; each gets one attempt.
+        let listener = TcpListener::bind(first.socket_addrs(|| None).unwrap()[0]).await.unwrap();
+        let first_server = tokio::spawn(serve(listener, vec![200]));
+        let listener = TcpListener::bind(second.socket_addrs(|| None).unwrap()[0]).await.unwrap();
+        let second_server = tokio::spawn(serve(listener, vec![404]));
+
+        let (delivered, remaining) = delivery(Some(path.clone())).replay().await.unwrap();
+        assert_eq!((delivered, remaining), (1, 0));
+        assert!(first_server.await.unwrap()[0].ends_with(&payload().body));
+        assert_eq!(second_server.await.unwrap().len(), 1);
+        assert!(read(&path).is_empty());
+        let rejected = read(&rejected_path(&path));
+        assert_eq!(rejected.len(), 2);
+        assert_eq!((rejected[1].url.as_str(), rejected[1].attempts), (second.as_str(), 4));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ntfy_sends_title_and_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, vec![200]));
+
+        let sink = Ntfy::new(&format!("http://{}/alerts?token=tk_secret", addr), Duration::from_secs(5)).unwrap();
+        assert_eq!(sink.name(), format!("ntfy http://{}/alerts", addr));
+
+        let target = Target::parse(&format!("Alice={}:phone@10m", Keys::generate().public_key()), None).unwrap();
+        let alert = Alert::Recovered { target, silent_secs: 900, location: None };
+        sink.send(&alert).await.unwrap();
+
+        let request = server.await.unwrap().remove(0);
+        let lower = request.to_ascii_lowercase();
+        assert!(request.starts_with("POST /alerts HTTP/1.1"));
+        assert!(lower.contains("title: alice (phone) back online"));
+        assert!(lower.contains("authorization: bearer tk_secret"));
+        assert!(request.ends_with("RECOVERED: Location updates from Alice (phone) resumed after 15m"));
+    }
+
+    #[tokio::test]
+    async fn ntfy_times_out() {
+        // Accepts the connection but never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move { listener.accept().await });
+
+        let sink = Ntfy::new(&format!("http://{}/alerts", addr), Duration::from_millis(200)).unwrap();
+        let target = Target::parse(&format!("Alice={}:phone@10m", Keys::generate().public_key()), None).unwrap();
+        let alert = Alert::Recovered { target, silent_secs: 900, location: None };
+        let started = std::time::Instant::now();
+        assert!(sink.send(&alert).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(server);
+    }
+
+    #[test]
+    fn gotify_requires_token() {
+        let timeout = Duration::from_secs(10);
+        assert!(Gotify::new("https://gotify.example.com", timeout).is_err());
+        let sink = Gotify::new("https://gotify.example.com/?token=abc", timeout).unwrap();
+        assert_eq!(sink.url.as_str(), "https://gotify.example.com/message");
+    }
+}
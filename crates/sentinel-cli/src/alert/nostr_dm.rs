@@ -0,0 +1,139 @@
+use async_trait::async_trait;
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use super::{Alert, AlertSink, SinkContext, SinkError};
+use crate::parse_pubkey;
+
+/// Sends alerts as NIP-17 private DMs, signed with the follower identity
+/// (the stored default identity or `--decrypt-with`).
+/// Each recipient gets the DM on their kind 10050 inbox relays if they
+/// publish any, otherwise on the follow relays.
+pub struct NostrDm {
+    client: Client,
+    recipients: Vec<PublicKey>,
+    inboxes: HashMap<PublicKey, Vec<RelayUrl>>,
+}
+
+impl NostrDm {
+    pub async fn new(recipients: &str, ctx: &SinkContext<'_>) -> Result<Self, SinkError> {
+        let keys = ctx
+            .keys
+            .ok_or_else(|| {
+                SinkError::Config(
+                    "nostr-dm needs an identity to sign with (`sentinel identity` or --decrypt-with)".into(),
+                )
+            })?;
+        let recipients = recipients
+            .split(',')
+            .map(|pk| parse_pubkey(pk.trim()).map_err(|e| SinkError::Config(e.to_string())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let client = Client::new(keys.clone());
+        for relay in ctx.relays {
+            client
+                .add_relay(relay)
+                .await
+                .map_err(|e| SinkError::Nostr(e.to_string()))?;
+        }
+        client.connect().await;
+
+        let filter = Filter::new().kind(Kind::InboxRelays).authors(recipients.clone());
+        let inboxes = match client.fetch_events(vec![filter], Some(Duration::from_secs(10))).await {
+            Ok(events) => inbox_relays(events.into_iter()),
+            Err(e) => {
+                eprintln!("Could not fetch DM inbox relays: {}", e);
+                HashMap::new()
+            }
+        };
+        for relays in inboxes.values() {
+            for relay in relays {
+                let _ = client.add_relay(relay).await;
+            }
+        }
+        client.connect().await;
+
+        Ok(Self { client, recipients, inboxes })
+    }
+}
+
+/// Newest kind 10050 relay list per author.
+fn inbox_relays(events: impl Iterator<Item = Event>) -> HashMap<PublicKey, Vec<RelayUrl>> {
+    let mut newest: HashMap<PublicKey, Event> = HashMap::new();
+    for event in events {
+        match newest.get(&event.pubkey) {
+            Some(seen) if seen.created_at >= event.created_at => {}
+            _ => {
+                newest.insert(event.pubkey, event);
+            }
+        }
+    }
+    newest
+        .into_iter()
+        .map(|(author, event)| {
+            let relays = event
+                .tags
+                .iter()
+                .filter(|tag| tag.kind() == TagKind::Relay)
+                .filter_map(|tag| tag.content())
+                .filter_map(|url| RelayUrl::parse(url).ok())
+                .collect();
+            (author, relays)
+        })
+        .collect()
+}
+
+#[async_trait]
+impl AlertSink for NostrDm {
+    fn name(&self) -> String {
+        format!("nostr-dm ({} recipients)", self.recipients.len())
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), SinkError> {
+        let message = alert.message();
+        let mut failed = Vec::new();
+        for recipient in &self.recipients {
+            let result = match self.inboxes.get(recipient) {
+                Some(relays) if !relays.is_empty() => {
+                    self.client
+                        .send_private_msg_to(relays.clone(), *recipient, message.clone(), [])
+                        .await
+                }
+                _ => self.client.send_private_msg(*recipient, message.clone(), []).await,
+            };
+            if let Err(e) = result {
+                failed.push(format!("{}: {}", recipient.to_bech32().unwrap_or_default(), e));
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(SinkError::Nostr(failed.join("; ")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inbox_relays_uses_newest_list() {
+        let keys = Keys::generate();
+        let list = |relay: &str, ts: u64| {
+            EventBuilder::new(Kind::InboxRelays, "")
+                .tag(Tag::custom(TagKind::Relay, [relay]))
+                .custom_created_at(Timestamp::from(ts))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+        let inboxes = inbox_relays(
+            vec![list("wss://new.example.com", 2), list("wss://old.example.com", 1)].into_iter(),
+        );
+        assert_eq!(
+            inboxes[&keys.public_key()],
+            vec![RelayUrl::parse("wss://new.example.com").unwrap()]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! RFC 3339 / ISO 8601 timestamps as used by gpsd, GPX and exports.
+
+use nostr_sdk::prelude::*;
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Proleptic Gregorian (year, month, day) for days since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Number of days in `month` of `year`.
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Seconds since the epoch for a UTC date and time of day. Impossible dates
+/// (Feb 31) and times past the end of the day are rejected.
+pub fn unix_time(year: i64, month: u32, day: u32, secs_of_day: u32) -> Option<Timestamp> {
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) || secs_of_day >= 86_400 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400 + secs_of_day as i64;
+    u64::try_from(secs).ok().map(Timestamp::from)
+}
+
+/// Parse `YYYY-MM-DD` (midnight UTC) or `YYYY-MM-DDTHH:MM:SS[.fff][Z|±HH:MM]`.
+/// Fractional seconds are dropped; a missing offset means UTC.
+pub fn parse(s: &str) -> Option<Timestamp> {
+    let s = s.trim();
+    let (date, time) = match s.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+
+    let Some(time) = time else {
+        return unix_time(year, month, day, 0);
+    };
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, ""),
+    };
+    let clock = clock.split('.').next()?;
+    let mut fields = clock.splitn(3, ':');
+    let hours: u32 = fields.next()?.parse().ok()?;
+    let minutes: u32 = fields.next()?.parse().ok()?;
+    let seconds: u32 = match fields.next() {
+        Some(s) => s.parse().ok()?,
+        None => 0,
+    };
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    // Unix time has no leap seconds: 23:59:60 counts as 23:59:59.
+    let seconds = seconds.min(59);
+    let offset_secs: i64 = match offset {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (h, m) = offset[1..].split_once(':').unwrap_or((&offset[1..], "0"));
+            sign * (h.parse::<i64>().ok()? * 3600 + m.parse::<i64>().ok()? * 60)
+        }
+    };
+    let local = unix_time(year, month, day, hours * 3600 + minutes * 60 + seconds)?;
+    u64::try_from(local.as_u64() as i64 - offset_secs).ok().map(Timestamp::from)
+}
+
+/// RFC 5322 date-time in UTC, as used in mail headers
+/// ("Thu, 01 Oct 2026 12:30:05 +0000").
+pub fn rfc5322(ts: Timestamp) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] =
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let days = (ts.as_u64() / 86_400) as i64;
+    let secs = ts.as_u64() % 86_400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dates_and_times() {
+        assert_eq!(parse("1970-01-01"), Some(Timestamp::from(0)));
+        assert_eq!(parse("2026-10-01"), Some(Timestamp::from(1_790_812_800)));
+        assert_eq!(parse("2026-10-01T12:30:05.250Z"), Some(Timestamp::from(1_790_857_805)));
+        assert_eq!(parse("2026-10-01T14:30:05+02:00"), Some(Timestamp::from(1_790_857_805)));
+        assert_eq!(parse("2026-10-01T12:30:05"), Some(Timestamp::from(1_790_857_805)));
+        assert_eq!(parse("2024-02-29T00:00:00Z"), Some(Timestamp::from(1_709_164_800)));
+        assert_eq!(parse("2026-13-01"), None);
+        assert_eq!(parse("2026-02-31"), None);
+        assert_eq!(parse("2023-02-29"), None);
+        assert_eq!(parse("2026-04-31T00:00:00Z"), None);
+        assert_eq!(parse("2016-12-31T23:59:60Z"), parse("2016-12-31T23:59:59Z"));
+        assert_eq!(unix_time(2026, 10, 1, 86_400), None);
+        assert_eq!(parse("yesterday"), None);
+        for ts in [0, 951_782_400, 1_790_857_805] {
+            let ts = Timestamp::from(ts);
+            assert_eq!(parse(&ts.to_human_datetime()), Some(ts));
+        }
+    }
+
+    #[test]
+    fn mail_dates() {
+        assert_eq!(rfc5322(Timestamp::from(0)), "Thu, 01 Jan 1970 00:00:00 +0000");
+        assert_eq!(rfc5322(Timestamp::from(1_790_857_805)), "Thu, 01 Oct 2026 12:30:05 +0000");
+        assert_eq!(rfc5322(Timestamp::from(1_709_164_800)), "Thu, 29 Feb 2024 00:00:00 +0000");
+        for days in [0, 59, 10_957, 20_727] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
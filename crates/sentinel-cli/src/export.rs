@@ -0,0 +1,210 @@
+//! `sentinel export`: write a track as GPX, GeoJSON or KML with
+//! `sentinel_core::export`, from relays or from a JSONL dump.
+
+use clap::Args;
+use nostr_sdk::prelude::*;
+use sentinel_core::event_builder::LocationData;
+use sentinel_core::event_parser::ParsedLocation;
+use sentinel_core::export::{self, ExportFormat};
+use sentinel_core::kinds;
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::{fetch_track, load_keys, parse_duration_str, parse_pubkey, parse_time, read_location, QueryWindow};
+
+#[derive(Debug, Args)]
+pub struct ExportArgs {
+    /// Public key (hex or npub) whose history track to fetch from relays
+    #[arg(long, required_unless_present = "input", conflicts_with = "input")]
+    pubkey: Option<String>,
+
+    /// Read a JSONL dump instead ("-" for stdin): Nostr events, `query --format
+    /// ndjson` records or `sentinel nmea` output
+    #[arg(long)]
+    input: Option<String>,
+
+    /// Start of the track, as a duration ago or a date (default: 24h from relays, all of a dump)
+    #[arg(long)]
+    since: Option<String>,
+
+    /// End of the track, as a duration ago or a date (default: now)
+    #[arg(long)]
+    until: Option<String>,
+
+    /// Events per relay request; full pages are followed back to --since
+    #[arg(long, default_value_t = 500)]
+    limit: usize,
+
+    /// How long to wait for each relay request
+    #[arg(long, default_value = "10s")]
+    timeout: String,
+
+    /// Relay URLs
+    #[arg(long, default_value = "wss://zooid.atlantislabs.space")]
+    relays: Vec<String>,
+
+    /// Filter by d-tag
+    #[arg(long)]
+    d_tag: Option<String>,
+
+    /// nsec to decrypt encrypted and gift-wrapped locations (default: the stored identity)
+    #[arg(long)]
+    decrypt_with: Option<String>,
+
+    /// Stored identity to decrypt with instead of the default
+    #[arg(long, conflicts_with = "decrypt_with")]
+    identity: Option<String>,
+
+    /// gpx, geojson or kml (default: from the --output extension)
+    #[arg(long)]
+    format: Option<ExportFormat>,
+
+    /// Write to this file instead of stdout
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+pub async fn cmd_export(args: ExportArgs) -> Result<()> {
+    let format = match (args.format, &args.output) {
+        (Some(format), _) => format,
+        (None, Some(path)) => path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or("Cannot tell the format from the output file; use --format")?
+            .parse()?,
+        (None, None) => return Err("Choose an export format with --format gpx|geojson|kml".into()),
+    };
+    let keys = load_keys(args.decrypt_with.as_deref(), args.identity.as_deref())?;
+    let window = QueryWindow {
+        since: args.since.as_deref().map(parse_time).transpose()?,
+        until: args.until.as_deref().map(parse_time).transpose()?,
+        limit: args.limit,
+        timeout: parse_duration_str(&args.timeout)?,
+    };
+    window.check()?;
+    let (since, until) = (window.since, window.until);
+
+    let mut track = match (&args.input, &args.pubkey) {
+        (Some(input), _) => read_dump(input, keys.as_ref())?,
+        (None, Some(pubkey)) => {
+            let window = QueryWindow {
+                since: Some(match since {
+                    Some(since) => since,
+                    None => parse_time("24h")?,
+                }),
+                until: Some(until.unwrap_or_else(Timestamp::now)),
+                ..window
+            };
+            fetch_track(parse_pubkey(pubkey)?, &window, &args.relays, args.d_tag.as_deref(), keys.as_ref())
+                .await?
+                .into_iter()
+                .map(|(_, _, loc)| loc)
+                .collect()
+        }
+        (None, None) => unreachable!("clap requires --pubkey or --input"),
+    };
+    track.retain(|loc| {
+        since.is_none_or(|since| loc.timestamp >= since)
+            && until.is_none_or(|until| loc.timestamp <= until)
+            && args.d_tag.as_deref().is_none_or(|d| d == loc.d_tag)
+    });
+
+    let text = export::export(format, &track);
+    match &args.output {
+        Some(path) => {
+            std::fs::write(path, text)?;
+            eprintln!("Wrote {} points to {}", track.len(), path.display());
+        }
+        None => {
+            print!("{}", text);
+            eprintln!("{} points", track.len());
+        }
+    }
+    Ok(())
+}
+
+/// Locations from a JSONL dump, one per line: a Nostr location event (read
+/// with `keys` if encrypted), a `ParsedLocation` (as printed by `query
+/// --format ndjson`) or a timestamped `LocationData` (as printed by `sentinel
+/// nmea`). Other lines are counted and skipped.
+pub fn read_dump(input: &str, keys: Option<&Keys>) -> Result<Vec<ParsedLocation>> {
+    let reader: Box<dyn BufRead> = if input == "-" {
+        Box::new(std::io::stdin().lock())
+    } else {
+        Box::new(std::io::BufReader::new(std::fs::File::open(input)?))
+    };
+    let mut locations = Vec::new();
+    let mut skipped = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_dump_line(&line, keys) {
+            Some(location) => locations.push(location),
+            None => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        eprintln!("Skipped {} lines of {} without a readable location", skipped, input);
+    }
+    Ok(locations)
+}
+
+fn parse_dump_line(line: &str, keys: Option<&Keys>) -> Option<ParsedLocation> {
+    if let Ok(event) = Event::from_json(line) {
+        if event.verify().is_err() {
+            return None;
+        }
+        return read_location(&event, &[], keys)?.ok();
+    }
+    if let Ok(location) = serde_json::from_str::<ParsedLocation>(line) {
+        return Some(location);
+    }
+    let data: LocationData = serde_json::from_str(line).ok()?;
+    Some(ParsedLocation {
+        geohash: data.geohash,
+        lat: data.lat,
+        lon: data.lon,
+        accuracy: data.accuracy,
+        d_tag: data.d_tag,
+        timestamp: data.timestamp?,
+        kind: kinds::PUBLIC_LOCATION_HISTORY,
+        pubkey: String::new(),
+        speed: data.speed,
+        course: data.course,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentinel_core::config::TrackingConfig;
+    use sentinel_core::event_builder;
+
+    #[test]
+    fn dump_lines() {
+        let keys = Keys::generate();
+        let config = TrackingConfig { d_tag: "car".into(), history: true, ..TrackingConfig::default() };
+        let event = event_builder::build_location_events(60.1699, 24.9384, Some(10.0), &keys, &config)
+            .unwrap()
+            .remove(0);
+        let from_event = parse_dump_line(&event.as_json(), None).unwrap();
+        assert_eq!((from_event.d_tag.as_str(), from_event.kind), ("car", kinds::PUBLIC_LOCATION_HISTORY));
+        assert_eq!(from_event.pubkey, keys.public_key().to_hex());
+
+        let record = serde_json::to_string(&from_event).unwrap();
+        assert_eq!(parse_dump_line(&record, None).unwrap().timestamp, event.created_at);
+
+        let nmea = r#"{"geohash":"ud9wr3xe","lat":60.1699,"lon":24.9384,"accuracy":6.0,"d_tag":"boat","encrypted":false,"timestamp":1790857805,"speed":2.5}"#;
+        let from_nmea = parse_dump_line(nmea, None).unwrap();
+        assert_eq!((from_nmea.d_tag.as_str(), from_nmea.timestamp.as_u64()), ("boat", 1_790_857_805));
+        assert_eq!((from_nmea.speed, from_nmea.course), (Some(2.5), None));
+        assert!(parse_dump_line(&nmea.replace("1790857805", "null"), None).is_none());
+
+        let mut forged: serde_json::Value = serde_json::from_str(&event.as_json()).unwrap();
+        forged["created_at"] = 1.into();
+        assert!(parse_dump_line(&forged.to_string(), None).is_none());
+        assert!(parse_dump_line("not json", None).is_none());
+    }
+}
@@ -0,0 +1,654 @@
+use nostr_sdk::prelude::*;
+use sentinel_core::event_parser::{self, ParsedLocation};
+use sentinel_core::geofence::{Geofence, GeofenceTracker};
+use sentinel_core::{event_builder, kinds};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// History events fetched per target at startup, for devices that publish
+/// only history.
+const HISTORY_BACKFILL: usize = 20;
+
+use crate::alert::{Alert, Alerter};
+use crate::relay_auth;
+use crate::relay_health::RelayHealth;
+use crate::{location_kinds, parse_duration_str, parse_pubkey, read_location};
+
+/// One followed device: a pubkey, optionally narrowed to a single `d` tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub name: String,
+    pub pubkey: PublicKey,
+    /// `None` follows every `d` tag published by `pubkey`.
+    pub d_tag: Option<String>,
+    pub alert_after: Duration,
+}
+
+impl Target {
+    /// Parse `[<name>=]<pubkey>[:<d_tag>][@<alert_after>]`, e.g.
+    /// `Alice=npub1...:phone@10m`. `alert_after` falls back to `default_after`.
+    pub fn parse(spec: &str, default_after: Option<Duration>) -> Result<Self> {
+        let (rest, after) = match spec.rsplit_once('@') {
+            Some((rest, after)) => (rest, Some(parse_duration_str(after)?)),
+            None => (spec, default_after),
+        };
+        let (name, rest) = match rest.split_once('=') {
+            Some((name, rest)) => (Some(name.trim().to_string()), rest),
+            None => (None, rest),
+        };
+        let (pubkey, d_tag) = match rest.split_once(':') {
+            Some((pubkey, d_tag)) => (pubkey, Some(d_tag.to_string())),
+            None => (rest, None),
+        };
+
+        Self::new(name, pubkey.trim(), d_tag, after)
+    }
+
+    fn new(
+        name: Option<String>,
+        pubkey: &str,
+        d_tag: Option<String>,
+        alert_after: Option<Duration>,
+    ) -> Result<Self> {
+        let pubkey = parse_pubkey(pubkey)?;
+        let alert_after = alert_after
+            .ok_or_else(|| format!("No alert duration for {} (use --alert-after)", pubkey))?;
+        let name = name.unwrap_or_else(|| short_npub(&pubkey));
+        Ok(Self { name, pubkey, d_tag, alert_after })
+    }
+
+    fn matches(&self, pubkey: &PublicKey, d_tag: &str) -> bool {
+        self.pubkey == *pubkey && self.d_tag.as_deref().is_none_or(|d| d == d_tag)
+    }
+
+    /// Human-readable label, e.g. "Alice (phone)".
+    pub fn label(&self) -> String {
+        match &self.d_tag {
+            Some(d) => format!("{} ({})", self.name, d),
+            None => self.name.clone(),
+        }
+    }
+}
+
+/// Entry of a roster file (JSON array).
+#[derive(Debug, Deserialize)]
+struct RosterEntry {
+    name: Option<String>,
+    pubkey: String,
+    d_tag: Option<String>,
+    alert_after: Option<String>,
+}
+
+/// Collect targets from `--pubkey`, `--target` and an optional roster file.
+pub fn load_targets(
+    pubkeys: &[String],
+    specs: &[String],
+    roster: Option<&str>,
+    default_after: Option<&str>,
+) -> Result<Vec<Target>> {
+    let default_after = default_after.map(parse_duration_str).transpose()?;
+
+    let mut targets = Vec::new();
+    if let Some(path) = roster {
+        let entries: Vec<RosterEntry> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        for entry in entries {
+            let after = entry.alert_after.as_deref().map(parse_duration_str).transpose()?;
+            targets.push(Target::new(
+                entry.name,
+                &entry.pubkey,
+                entry.d_tag,
+                after.or(default_after),
+            )?);
+        }
+    }
+    for pubkey in pubkeys {
+        targets.push(Target::new(None, pubkey, None, default_after)?);
+    }
+    for spec in specs {
+        targets.push(Target::parse(spec, default_after)?);
+    }
+
+    if targets.is_empty() {
+        return Err("Nothing to follow (use --pubkey, --target or --roster)".into());
+    }
+    Ok(targets)
+}
+
+/// Silence detector for one target, driven by event `created_at` and
+/// `expiration` rather than receipt time, so replayed or backfilled events
+/// cannot hide real silence.
+#[derive(Debug, Clone)]
+struct Silence {
+    alert_after: u64,
+    repeat_max: u64,
+    /// Reference point while nothing has been seen yet.
+    started: Timestamp,
+    last_seen: Option<Timestamp>,
+    expires_at: Option<Timestamp>,
+    alerts_sent: u32,
+    next_alert: Option<Timestamp>,
+}
+
+impl Silence {
+    fn new(alert_after: Duration, repeat_max: Duration, now: Timestamp) -> Self {
+        Self {
+            alert_after: alert_after.as_secs(),
+            repeat_max: repeat_max.as_secs().max(alert_after.as_secs()),
+            started: now,
+            last_seen: None,
+            expires_at: None,
+            alerts_sent: 0,
+            next_alert: None,
+        }
+    }
+
+    /// When the latest location goes stale: `alert_after` past its
+    /// `created_at`, or its expiration if that comes first.
+    fn stale_at(&self) -> Timestamp {
+        let base = self.last_seen.unwrap_or(self.started) + self.alert_after;
+        match self.expires_at {
+            Some(exp) if exp < base => exp,
+            _ => base,
+        }
+    }
+
+    fn silent_secs(&self, now: Timestamp) -> u64 {
+        now.as_u64().saturating_sub(self.last_seen.unwrap_or(self.started).as_u64())
+    }
+
+    /// Record an event. Events not newer than the latest one are ignored.
+    /// Returns the silence that ended if an alert was active and this event
+    /// is fresh.
+    fn observe(
+        &mut self,
+        created_at: Timestamp,
+        expiration: Option<Timestamp>,
+        now: Timestamp,
+    ) -> Option<u64> {
+        if self.last_seen.is_some_and(|seen| created_at <= seen) {
+            return None;
+        }
+        let silent = created_at.as_u64().saturating_sub(
+            self.last_seen.unwrap_or(self.started).as_u64(),
+        );
+        self.last_seen = Some(created_at);
+        self.expires_at = expiration;
+
+        if self.alerts_sent > 0 && now < self.stale_at() {
+            self.alerts_sent = 0;
+            self.next_alert = None;
+            return Some(silent);
+        }
+        None
+    }
+
+    /// Returns the repeat count (1 for the first alert) when an alert is due.
+    /// Repeats back off exponentially from `alert_after` up to `repeat_max`.
+    fn check(&mut self, now: Timestamp) -> Option<u32> {
+        if now < self.stale_at() || self.next_alert.is_some_and(|next| now < next) {
+            return None;
+        }
+        let backoff = self
+            .alert_after
+            .saturating_mul(1u64 << self.alerts_sent.min(32))
+            .min(self.repeat_max);
+        self.alerts_sent += 1;
+        self.next_alert = Some(now + backoff);
+        Some(self.alerts_sent)
+    }
+}
+
+/// How silence and relay alerts are raised.
+#[derive(Debug, Clone)]
+pub struct AlertPolicy {
+    /// Cap for the doubling interval between repeated silence alerts.
+    pub repeat_max: Duration,
+    /// How long a relay may be disconnected before it is reported.
+    pub relay_grace: Duration,
+    /// Drop silence alerts, instead of marking them degraded, when no relay
+    /// was connected during the silent window.
+    pub suppress_degraded: bool,
+}
+
+struct TargetState {
+    target: Target,
+    silence: Silence,
+    geofences: GeofenceTracker,
+    /// Newest decoded location, for map links in alerts.
+    last_location: Option<ParsedLocation>,
+}
+
+/// Alert state for every followed target and the relays they are read from.
+struct Follower {
+    states: Vec<TargetState>,
+    authors: Vec<PublicKey>,
+    keys: Option<Keys>,
+    relays: RelayHealth,
+    suppress_degraded: bool,
+}
+
+impl Follower {
+    fn new(
+        targets: Vec<Target>,
+        zones: Vec<Geofence>,
+        keys: Option<Keys>,
+        policy: &AlertPolicy,
+        now: Timestamp,
+    ) -> Self {
+        let mut authors: Vec<PublicKey> = targets.iter().map(|t| t.pubkey).collect();
+        authors.sort();
+        authors.dedup();
+
+        let states = targets
+            .into_iter()
+            .map(|target| TargetState {
+                silence: Silence::new(target.alert_after, policy.repeat_max, now),
+                geofences: GeofenceTracker::new(zones.clone()),
+                last_location: None,
+                target,
+            })
+            .collect();
+        Self {
+            states,
+            authors,
+            keys,
+            relays: RelayHealth::new(policy.relay_grace),
+            suppress_degraded: policy.suppress_degraded,
+        }
+    }
+
+    /// Filters for the last known location of every target: all of its
+    /// latest locations (one per `d` tag, so no limit is needed) and its few
+    /// newest history events, fetched apart so a busy history can't crowd
+    /// out another device.
+    fn backfill_filters(&self) -> Vec<Filter> {
+        let latest = [Kind::from(kinds::PUBLIC_LOCATION), Kind::from(kinds::ENCRYPTED_LOCATION)];
+        let history = [
+            Kind::from(kinds::PUBLIC_LOCATION_HISTORY),
+            Kind::from(kinds::ENCRYPTED_LOCATION_HISTORY),
+        ];
+        let mut filters = Vec::new();
+        for state in &self.states {
+            let target = &state.target;
+            let mut filter = Filter::new().author(target.pubkey);
+            if let Some(d) = &target.d_tag {
+                let mut identifiers = vec![d.clone()];
+                if let Some(keys) = &self.keys {
+                    identifiers.push(event_builder::recipient_d_tag(d, &keys.public_key()));
+                }
+                filter = filter.identifiers(identifiers);
+            }
+            for filter in [filter.clone().kinds(latest), filter.kinds(history).limit(HISTORY_BACKFILL)] {
+                if !filters.contains(&filter) {
+                    filters.push(filter);
+                }
+            }
+        }
+        filters
+    }
+
+    /// Feed a relay status change; returns an alert if a reported relay is back.
+    fn relay_status(&mut self, relay: &str, status: RelayStatus, now: Timestamp) -> Option<Alert> {
+        self.relays.update(relay, status, now).map(Alert::from)
+    }
+
+    /// Feed an event from a relay; returns recovery and geofence alerts.
+    fn observe(&mut self, event: &Event, now: Timestamp) -> Vec<Alert> {
+        let kind = event.kind.as_u16();
+        let location = read_location(event, &self.authors, self.keys.as_ref());
+
+        let (author, d_tag, created_at) = match &location {
+            Some(Ok(loc)) => match PublicKey::from_hex(&loc.pubkey) {
+                Ok(author) => (author, loc.d_tag.clone(), loc.timestamp),
+                Err(_) => return Vec::new(),
+            },
+            _ if kinds::is_location(kind) => {
+                (event.pubkey, event_parser::base_d_tag(event), event.created_at)
+            }
+            _ => return Vec::new(),
+        };
+        if let Some(Err(e)) = &location {
+            eprintln!("Skipping event {}: {}", event.id, e);
+        }
+        let expiration = event.tags.expiration().copied();
+
+        let mut alerts = Vec::new();
+        for state in self.states.iter_mut().filter(|s| s.target.matches(&author, &d_tag)) {
+            if let Some(Ok(loc)) = &location {
+                if state.last_location.as_ref().is_none_or(|last| last.timestamp < loc.timestamp) {
+                    state.last_location = Some(loc.clone());
+                }
+            }
+            if let Some(silent_secs) = state.silence.observe(created_at, expiration, now) {
+                alerts.push(Alert::Recovered {
+                    target: state.target.clone(),
+                    silent_secs,
+                    location: state.last_location.clone(),
+                });
+            }
+
+            if let Some(Ok(loc)) = &location {
+                match state.geofences.update(loc) {
+                    Ok(transitions) => alerts.extend(
+                        transitions
+                            .into_iter()
+                            .map(|transition| Alert::Geofence { target: state.target.clone(), transition }),
+                    ),
+                    Err(e) => eprintln!("Geofence check of event {} failed: {}", event.id, e),
+                }
+            }
+        }
+        alerts
+    }
+
+    /// Returns the relay and silence alerts due at `now`.
+    fn check(&mut self, now: Timestamp) -> Vec<Alert> {
+        let mut alerts: Vec<Alert> = self.relays.check(now).into_iter().map(Alert::from).collect();
+        for state in &mut self.states {
+            let window_start = state.silence.last_seen.unwrap_or(state.silence.started);
+            let degraded = !self.relays.any_connected_since(window_start);
+            if degraded && self.suppress_degraded {
+                continue;
+            }
+            if let Some(repeat) = state.silence.check(now) {
+                alerts.push(Alert::Silence {
+                    target: state.target.clone(),
+                    last_seen: state.silence.last_seen,
+                    location: state.last_location.clone(),
+                    silent_secs: state.silence.silent_secs(now),
+                    repeat,
+                    degraded,
+                });
+            }
+        }
+        alerts
+    }
+}
+
+pub async fn cmd_follow(
+    targets: Vec<Target>,
+    alerter: Alerter,
+    relays: &[String],
+    decrypt_with: Option<Keys>,
+    zones: Vec<Geofence>,
+    policy: AlertPolicy,
+) -> Result<()> {
+    for target in &targets {
+        eprintln!(
+            "Following {} [{}] — alert after {} of silence",
+            target.label(),
+            target.pubkey.to_bech32()?,
+            format_secs(target.alert_after.as_secs()),
+        );
+    }
+
+    let (client, auth) = relay_auth::client(decrypt_with.as_ref());
+    for relay in relays {
+        client.add_relay(relay).await?;
+    }
+
+    let follower = Follower::new(targets, zones, decrypt_with, &policy, Timestamp::now());
+    let follower = Arc::new(Mutex::new(follower));
+
+    // Deliver alerts one by one in the background, so slow sinks (webhook
+    // retries) never hold up the notification receivers.
+    let (alerts, mut queued) = tokio::sync::mpsc::unbounded_channel::<Alert>();
+    tokio::spawn(async move {
+        while let Some(alert) = queued.recv().await {
+            alerter.deliver(alert).await;
+        }
+    });
+
+    // The pool no longer forwards status changes, so watch each relay.
+    for (url, relay) in client.relays().await {
+        let url = url.to_string();
+        let mut notifications = relay.notifications();
+        follower.lock().unwrap().relay_status(&url, relay.status(), Timestamp::now());
+
+        let follower = Arc::clone(&follower);
+        let alerts = alerts.clone();
+        tokio::spawn(async move {
+            loop {
+                let notification = match notifications.recv().await {
+                    Ok(notification) => notification,
+                    // Missed messages; the next status change still counts.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if let RelayNotification::RelayStatus { status } = notification {
+                    let alert = follower.lock().unwrap().relay_status(&url, status, Timestamp::now());
+                    if let Some(alert) = alert {
+                        let _ = alerts.send(alert);
+                    }
+                }
+            }
+        });
+    }
+    client.connect().await;
+
+    let (authors, keys, backfill) = {
+        let follower = follower.lock().unwrap();
+        (follower.authors.clone(), follower.keys.clone(), follower.backfill_filters())
+    };
+
+    let mut gift_wrap_filter = None;
+    if let Some(ref keys) = keys {
+        // Gift wrap timestamps are randomized into the past.
+        gift_wrap_filter = Some(
+            Filter::new()
+                .kind(Kind::GiftWrap)
+                .pubkey(keys.public_key())
+                .since(Timestamp::now() - event_builder::GIFT_WRAP_TIMESTAMP_TWEAK_SECS),
+        );
+    }
+
+    // Fetch the last known locations so long-silent targets alert right away.
+    let mut filters = backfill;
+    filters.extend(gift_wrap_filter.clone());
+    let mut known: Vec<Event> = match relay_auth::fetch_events(&client, &auth, filters, Duration::from_secs(10)).await {
+        Ok(events) => events.into_iter().collect(),
+        Err(e) => {
+            eprintln!("Could not fetch last known locations: {}", e);
+            Vec::new()
+        }
+    };
+    known.sort_by_key(|e| e.created_at);
+
+    {
+        let mut follower = follower.lock().unwrap();
+        let now = Timestamp::now();
+        for event in &known {
+            // Baseline only: recoveries and geofence transitions need live events.
+            follower.observe(event, now);
+        }
+    }
+
+    let mut filters = vec![Filter::new()
+        .authors(authors)
+        .kinds(location_kinds())
+        .since(Timestamp::now())];
+    filters.extend(gift_wrap_filter);
+    client.subscribe(filters, None).await?;
+
+    // Spawn alert checker
+    let checker = Arc::clone(&follower);
+    let checker_alerts = alerts.clone();
+    tokio::spawn(async move {
+        loop {
+            let due = checker.lock().unwrap().check(Timestamp::now());
+            for alert in due {
+                let _ = checker_alerts.send(alert);
+            }
+            tokio::time::sleep(Duration::from_secs(10)).await;
+        }
+    });
+
+    // Process notifications
+    client
+        .handle_notifications(|notification| {
+            let follower = Arc::clone(&follower);
+            let alerts = alerts.clone();
+            async move {
+                if let RelayPoolNotification::Event { event, .. } = notification {
+                    for alert in follower.lock().unwrap().observe(&event, Timestamp::now()) {
+                        let _ = alerts.send(alert);
+                    }
+                }
+                Ok(false) // false = don't stop
+            }
+        })
+        .await?;
+
+    client.disconnect().await?;
+    Ok(())
+}
+
+/// Format seconds compactly, e.g. "1h05m", "10m", "45s".
+pub(crate) fn format_secs(secs: u64) -> String {
+    match (secs / 3600, (secs % 3600) / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, 0, _) => format!("{}h", h),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+fn short_npub(pubkey: &PublicKey) -> String {
+    let npub = pubkey.to_bech32().unwrap_or_else(|_| pubkey.to_hex());
+    format!("{}…", &npub[..12])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+
+    #[test]
+    fn parse_target_spec() {
+        let t = Target::parse(&format!("Alice={}:phone@10m", HEX), None).unwrap();
+        assert_eq!(t.name, "Alice");
+        assert_eq!(t.pubkey.to_hex(), HEX);
+        assert_eq!(t.d_tag.as_deref(), Some("phone"));
+        assert_eq!(t.alert_after, Duration::from_secs(600));
+        assert_eq!(t.label(), "Alice (phone)");
+
+        let t = Target::parse(HEX, Some(Duration::from_secs(60))).unwrap();
+        assert!(t.name.starts_with("npub1"));
+        assert_eq!(t.d_tag, None);
+        assert_eq!(t.alert_after, Duration::from_secs(60));
+
+        assert!(Target::parse(HEX, None).is_err());
+    }
+
+    #[test]
+    fn target_matches_d_tag() {
+        let any = Target::parse(HEX, Some(Duration::from_secs(60))).unwrap();
+        let car = Target::parse(&format!("{}:car", HEX), Some(Duration::from_secs(60))).unwrap();
+        let pk = PublicKey::from_hex(HEX).unwrap();
+        let other = Keys::generate().public_key();
+
+        assert!(any.matches(&pk, "phone"));
+        assert!(car.matches(&pk, "car"));
+        assert!(!car.matches(&pk, "phone"));
+        assert!(!any.matches(&other, "phone"));
+    }
+
+    #[test]
+    fn silence_uses_created_at_and_backs_off() {
+        let t0 = Timestamp::from(1_000_000);
+        let mut s = Silence::new(Duration::from_secs(600), Duration::from_secs(1800), t0);
+
+        // Startup fetch finds a location two hours old: alert immediately.
+        assert_eq!(s.observe(t0 - 7200u64, None, t0), None);
+        assert_eq!(s.check(t0), Some(1));
+        assert_eq!(s.silent_secs(t0), 7200);
+
+        // Replayed old events neither recover nor reset the timer.
+        assert_eq!(s.observe(t0 - 9000u64, None, t0), None);
+        assert_eq!(s.check(t0 + 599u64), None);
+
+        // Backoff: 10m, then 20m, then capped at 30m.
+        assert_eq!(s.check(t0 + 600u64), Some(2));
+        assert_eq!(s.check(t0 + 600u64 + 1199u64), None);
+        assert_eq!(s.check(t0 + 1800u64), Some(3));
+        assert_eq!(s.check(t0 + 3600u64), Some(4));
+
+        // A late backfill that is itself stale does not count as recovery.
+        assert_eq!(s.observe(t0 - 3600u64, None, t0 + 3700u64), None);
+
+        let now = t0 + 4000u64;
+        assert_eq!(s.observe(now - 5u64, None, now), Some(7595));
+        assert_eq!(s.check(now), None);
+    }
+
+    #[test]
+    fn silence_respects_expiration() {
+        let t0 = Timestamp::from(1_000_000);
+        let mut s = Silence::new(Duration::from_secs(3600), Duration::from_secs(3600), t0);
+
+        s.observe(t0, Some(t0 + 300u64), t0);
+        assert_eq!(s.check(t0 + 299u64), None);
+        assert_eq!(s.check(t0 + 300u64), Some(1));
+    }
+
+    #[test]
+    fn silence_during_relay_outage_is_degraded() {
+        let t0 = Timestamp::from(1_000_000);
+        let target = Target::parse(&format!("{}@10m", Keys::generate().public_key()), None).unwrap();
+        let mut policy = AlertPolicy {
+            repeat_max: Duration::from_secs(3600),
+            relay_grace: Duration::from_secs(60),
+            suppress_degraded: false,
+        };
+        let relay = "wss://relay.example.com";
+
+        let mut follower = Follower::new(vec![target.clone()], Vec::new(), None, &policy, t0);
+        follower.relay_status(relay, RelayStatus::Connecting, t0);
+        let alerts = follower.check(t0 + 600u64);
+        assert!(matches!(alerts[0], Alert::RelayDown { .. }));
+        assert!(matches!(alerts[1], Alert::Silence { degraded: true, .. }));
+
+        policy.suppress_degraded = true;
+        let mut follower = Follower::new(vec![target], Vec::new(), None, &policy, t0);
+        follower.relay_status(relay, RelayStatus::Connecting, t0);
+        assert_eq!(follower.check(t0 + 600u64).len(), 1);
+
+        // Once a relay is up again, silence is the device's.
+        follower.relay_status(relay, RelayStatus::Connected, t0 + 700u64);
+        let alerts = follower.check(t0 + 700u64);
+        assert!(matches!(alerts[..], [Alert::Silence { degraded: false, .. }]));
+    }
+
+    #[test]
+    fn backfill_per_target() {
+        let policy = AlertPolicy {
+            repeat_max: Duration::from_secs(3600),
+            relay_grace: Duration::from_secs(60),
+            suppress_degraded: false,
+        };
+        let spec = |d: &str| Target::parse(&format!("{}{}@10m", HEX, d), None).unwrap();
+        let targets = vec![spec(":phone"), spec(":car"), spec(""), spec("")];
+        let follower = Follower::new(targets, Vec::new(), None, &policy, Timestamp::from(1_000_000));
+
+        let filters = follower.backfill_filters();
+        // Latest and history for phone, car and the whole pubkey, once each.
+        assert_eq!(filters.len(), 6);
+        for filter in &filters {
+            let addressable = filter.kinds.as_ref().unwrap().contains(&Kind::from(kinds::PUBLIC_LOCATION));
+            assert_eq!(filter.limit, if addressable { None } else { Some(HISTORY_BACKFILL) });
+        }
+        assert!(filters[0].generic_tags.values().any(|values| values.contains("phone")));
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(format_secs(45), "45s");
+        assert_eq!(format_secs(600), "10m");
+        assert_eq!(format_secs(3900), "1h05m");
+        assert_eq!(format_secs(7200), "2h");
+    }
+}
@@ -0,0 +1,309 @@
+//! Stored identities: NIP-49 password-encrypted secret keys (`ncryptsec`) in
+//! `identities.json` under the config directory, plus the name of the default.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// scrypt cost for newly encrypted keys, as recommended by NIP-49.
+const LOG_N: u8 = 16;
+
+/// Password source for scripts and services; prompted for otherwise.
+pub const PASSWORD_ENV: &str = "SENTINEL_PASSWORD";
+
+#[derive(Debug, thiserror::Error)]
+pub enum IdentityError {
+    #[error("no identity named '{0}'")]
+    NotFound(String),
+    #[error("an identity named '{0}' already exists")]
+    Exists(String),
+    #[error("invalid identity name '{0}' (use letters, digits, '.', '-' and '_')")]
+    InvalidName(String),
+    #[error("wrong password for identity '{0}'")]
+    WrongPassword(String),
+    #[error("passwords don't match")]
+    PasswordMismatch,
+    #[error("no config directory (set SENTINEL_CONFIG_DIR or HOME)")]
+    NoConfigDir,
+    #[error("invalid key: {0}")]
+    Key(String),
+    #[error("{0}: {1}")]
+    File(PathBuf, String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    pub name: String,
+    pub npub: String,
+    pub ncryptsec: String,
+    pub created_at: u64,
+}
+
+impl Identity {
+    pub fn public_key(&self) -> Result<PublicKey, IdentityError> {
+        PublicKey::from_bech32(&self.npub).map_err(|e| IdentityError::Key(e.to_string()))
+    }
+
+    /// Decrypt the secret key, checking it against the stored npub.
+    pub fn unlock(&self, password: &str) -> Result<Keys, IdentityError> {
+        let encrypted = EncryptedSecretKey::from_bech32(&self.ncryptsec)
+            .map_err(|e| IdentityError::Key(e.to_string()))?;
+        let secret = encrypted
+            .to_secret_key(password)
+            .map_err(|_| IdentityError::WrongPassword(self.name.clone()))?;
+        let keys = Keys::new(secret);
+        if keys.public_key() != self.public_key()? {
+            return Err(IdentityError::Key(format!("'{}' doesn't match its npub", self.name)));
+        }
+        Ok(keys)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Identities {
+    default: Option<String>,
+    identities: Vec<Identity>,
+}
+
+/// The identity file. The first identity added becomes the default.
+pub struct Store {
+    path: PathBuf,
+    log_n: u8,
+}
+
+impl Store {
+    pub fn new(dir: &Path) -> Self {
+        Self { path: dir.join("identities.json"), log_n: LOG_N }
+    }
+
+    /// `$SENTINEL_CONFIG_DIR`, else `$XDG_CONFIG_HOME/sentinel` or `~/.config/sentinel`.
+    pub fn open() -> Result<Self, IdentityError> {
+        let dir = match std::env::var_os("SENTINEL_CONFIG_DIR") {
+            Some(dir) => PathBuf::from(dir),
+            None => std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+                .ok_or(IdentityError::NoConfigDir)?
+                .join("sentinel"),
+        };
+        Ok(Self::new(&dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<Identities, IdentityError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| IdentityError::File(self.path.clone(), e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Identities::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, identities: &Identities) -> Result<(), IdentityError> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(identities)
+            .map_err(|e| IdentityError::File(self.path.clone(), e.to_string()))?;
+        let tmp = self.path.with_extension("json.tmp");
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options.open(&tmp)?.write_all(json.as_bytes())?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    /// All identities and the name of the default.
+    pub fn list(&self) -> Result<(Vec<Identity>, Option<String>), IdentityError> {
+        let file = self.load()?;
+        Ok((file.identities, file.default))
+    }
+
+    /// The identity called `name`, or the default when `name` is `None`.
+    pub fn get(&self, name: Option<&str>) -> Result<Option<Identity>, IdentityError> {
+        let file = self.load()?;
+        let Some(name) = name.or(file.default.as_deref()) else {
+            return Ok(None);
+        };
+        match file.identities.iter().find(|id| id.name == name) {
+            Some(identity) => Ok(Some(identity.clone())),
+            None => Err(IdentityError::NotFound(name.to_string())),
+        }
+    }
+
+    /// Encrypt `keys` with `password` and store them as `name`.
+    pub fn add(
+        &self,
+        name: &str,
+        keys: &Keys,
+        password: &str,
+        security: KeySecurity,
+    ) -> Result<Identity, IdentityError> {
+        let encrypted = EncryptedSecretKey::new(keys.secret_key(), password, self.log_n, security)
+            .map_err(|e| IdentityError::Key(e.to_string()))?;
+        self.insert(name, keys.public_key(), encrypted)
+    }
+
+    /// Store an existing `ncryptsec` as `name`; `password` must unlock it.
+    pub fn add_encrypted(
+        &self,
+        name: &str,
+        ncryptsec: &str,
+        password: &str,
+    ) -> Result<Identity, IdentityError> {
+        let encrypted = EncryptedSecretKey::from_bech32(ncryptsec)
+            .map_err(|e| IdentityError::Key(e.to_string()))?;
+        let secret = encrypted
+            .to_secret_key(password)
+            .map_err(|_| IdentityError::WrongPassword(name.to_string()))?;
+        self.insert(name, Keys::new(secret).public_key(), encrypted)
+    }
+
+    fn insert(
+        &self,
+        name: &str,
+        pubkey: PublicKey,
+        encrypted: EncryptedSecretKey,
+    ) -> Result<Identity, IdentityError> {
+        let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_');
+        if name.is_empty() || !name.chars().all(valid) {
+            return Err(IdentityError::InvalidName(name.to_string()));
+        }
+        let mut file = self.load()?;
+        if file.identities.iter().any(|id| id.name == name) {
+            return Err(IdentityError::Exists(name.to_string()));
+        }
+        let identity = Identity {
+            name: name.to_string(),
+            npub: pubkey.to_bech32().map_err(|e| IdentityError::Key(e.to_string()))?,
+            ncryptsec: encrypted.to_bech32().map_err(|e| IdentityError::Key(e.to_string()))?,
+            created_at: Timestamp::now().as_u64(),
+        };
+        file.identities.push(identity.clone());
+        file.default.get_or_insert_with(|| name.to_string());
+        self.save(&file)?;
+        Ok(identity)
+    }
+
+    /// Make `name` the default identity.
+    pub fn set_default(&self, name: &str) -> Result<(), IdentityError> {
+        let mut file = self.load()?;
+        if !file.identities.iter().any(|id| id.name == name) {
+            return Err(IdentityError::NotFound(name.to_string()));
+        }
+        file.default = Some(name.to_string());
+        self.save(&file)
+    }
+}
+
+/// Whether [`read_password`] can get a password without blocking on a
+/// missing terminal: [`PASSWORD_ENV`] is set or stdin is a terminal.
+pub fn password_available() -> bool {
+    std::env::var_os(PASSWORD_ENV).is_some() || std::io::stdin().is_terminal()
+}
+
+/// Read a password from [`PASSWORD_ENV`], else from the terminal without
+/// echo (or a line of stdin when it isn't a terminal). With `confirm`, an
+/// interactive prompt asks twice.
+pub fn read_password(prompt: &str, confirm: bool) -> Result<String, IdentityError> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    let password = read_secret(prompt)?;
+    if confirm && std::io::stdin().is_terminal() && read_secret("Repeat password: ")? != password {
+        return Err(IdentityError::PasswordMismatch);
+    }
+    Ok(password)
+}
+
+/// Read a line from stdin, prompting without echo on a terminal.
+pub fn read_secret(prompt: &str) -> Result<String, IdentityError> {
+    let interactive = std::io::stdin().is_terminal();
+    let stty = |arg: &str| {
+        let _ = std::process::Command::new("stty").arg(arg).status();
+    };
+    if interactive {
+        eprint!("{}", prompt);
+        std::io::stderr().flush()?;
+        stty("-echo");
+    }
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line);
+    if interactive {
+        stty("echo");
+        eprintln!();
+    }
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(test: &str) -> Store {
+        let dir = std::env::temp_dir().join(format!("sentinel-identity-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // Cheap scrypt so the tests stay fast.
+        Store { path: dir.join("identities.json"), log_n: 4 }
+    }
+
+    #[test]
+    fn identities_round_trip() {
+        let store = store("round-trip");
+        assert_eq!(store.get(None).unwrap(), None);
+
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        store.add("alice", &alice, "pw1", KeySecurity::Medium).unwrap();
+        store.add("bob", &bob, "pw2", KeySecurity::Weak).unwrap();
+        assert!(matches!(
+            store.add("alice", &bob, "pw2", KeySecurity::Weak),
+            Err(IdentityError::Exists(_))
+        ));
+        assert!(matches!(
+            store.add("a b", &bob, "pw2", KeySecurity::Weak),
+            Err(IdentityError::InvalidName(_))
+        ));
+
+        // The first identity is the default until another is chosen.
+        let default = store.get(None).unwrap().unwrap();
+        assert_eq!(default.name, "alice");
+        assert_eq!(default.public_key().unwrap(), alice.public_key());
+        assert!(default.ncryptsec.starts_with("ncryptsec1"));
+        assert_eq!(default.unlock("pw1").unwrap().secret_key(), alice.secret_key());
+        assert!(matches!(default.unlock("nope"), Err(IdentityError::WrongPassword(_))));
+
+        store.set_default("bob").unwrap();
+        let (identities, default) = store.list().unwrap();
+        assert_eq!(identities.len(), 2);
+        assert_eq!(default.as_deref(), Some("bob"));
+        assert!(matches!(store.set_default("carol"), Err(IdentityError::NotFound(_))));
+        assert!(matches!(store.get(Some("carol")), Err(IdentityError::NotFound(_))));
+
+        // An exported ncryptsec imports as-is with its password.
+        let exported = store.get(Some("bob")).unwrap().unwrap().ncryptsec;
+        let other = self::store("round-trip-import");
+        assert!(other.add_encrypted("bob", &exported, "wrong").is_err());
+        let imported = other.add_encrypted("bob", &exported, "pw2").unwrap();
+        assert_eq!(imported.ncryptsec, exported);
+        assert_eq!(imported.public_key().unwrap(), bob.public_key());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(store.path()).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_dir_all(store.path().parent().unwrap()).unwrap();
+        std::fs::remove_dir_all(other.path().parent().unwrap()).unwrap();
+    }
+}
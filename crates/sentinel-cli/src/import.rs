@@ -0,0 +1,397 @@
+//! `sentinel import`: turn location history from other apps (Google
+//! Takeout, OwnTracks recorder, GPX) into sentinel history events, written
+//! as JSON lines or published to relays.
+
+use clap::{Args, ValueEnum};
+use nostr_sdk::prelude::*;
+use sentinel_core::config::TrackingConfig;
+use sentinel_core::event_builder::{self, LocationData};
+use serde_json::Value;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::datetime;
+use crate::publish::{PublishArgs, Publisher};
+use crate::source::nmea::UERE_M;
+use crate::source::Fix;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// Google Takeout: Records.json or the newer Timeline.json
+    Takeout,
+    /// OwnTracks recorder .rec files
+    Owntracks,
+    /// GPX track, route and waypoints
+    Gpx,
+}
+
+#[derive(Debug, Args)]
+pub struct ImportArgs {
+    /// File to import
+    input: PathBuf,
+
+    /// Input format (default: from the extension: .json, .rec, .gpx)
+    #[arg(long, value_enum)]
+    from: Option<ImportFormat>,
+
+    /// Write the signed events as JSON lines to this file ("-" for stdout)
+    #[arg(long, conflicts_with = "to_relays")]
+    output: Option<String>,
+
+    /// Publish the events to --relays
+    #[arg(long = "publish")]
+    to_relays: bool,
+
+    /// Publish at most this many events per second
+    #[arg(long, default_value_t = 5.0)]
+    rate: f64,
+
+    /// Print the parsed locations as JSON lines without signing, writing or publishing
+    #[arg(long)]
+    dry_run: bool,
+
+    #[command(flatten)]
+    publish: PublishArgs,
+}
+
+pub async fn cmd_import(args: ImportArgs) -> Result<()> {
+    if !args.dry_run && args.output.is_none() && !args.to_relays {
+        return Err("Choose --output <file>, --publish or --dry-run".into());
+    }
+    if !args.rate.is_finite() || args.rate <= 0.0 {
+        return Err("--rate must be positive".into());
+    }
+    let format = match args.from {
+        Some(format) => format,
+        None => match args.input.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => ImportFormat::Takeout,
+            Some("rec") => ImportFormat::Owntracks,
+            Some("gpx") => ImportFormat::Gpx,
+            _ => return Err("Cannot tell the input format from the file name; use --from".into()),
+        },
+    };
+    let config = args.publish.import_config()?;
+
+    let text = std::fs::read_to_string(&args.input)?;
+    let (mut fixes, undated) = parse(format, &text)?;
+    fixes.sort_by_key(|fix| fix.time);
+    let (points, invalid) = locations(fixes, &config);
+    if undated > 0 {
+        eprintln!("Skipped {} points without a time", undated);
+    }
+    if invalid > 0 {
+        eprintln!("Skipped {} points with invalid coordinates or accuracy", invalid);
+    }
+    let locations: Vec<&LocationData> = points.iter().map(|(_, location)| location).collect();
+    match (locations.first(), locations.last()) {
+        (Some(first), Some(last)) => eprintln!(
+            "{} locations from {} to {}",
+            locations.len(),
+            first.timestamp.unwrap_or_default().to_human_datetime(),
+            last.timestamp.unwrap_or_default().to_human_datetime()
+        ),
+        _ => return Err(format!("No locations in {}", args.input.display()).into()),
+    }
+
+    if args.dry_run {
+        let mut out = std::io::stdout().lock();
+        for location in &locations {
+            writeln!(out, "{}", serde_json::to_string(location)?)?;
+        }
+        return Ok(());
+    }
+
+    let keys = args.publish.keys()?;
+    if let Some(output) = &args.output {
+        let mut out: Box<dyn Write> = if output == "-" {
+            Box::new(std::io::stdout().lock())
+        } else {
+            Box::new(std::io::BufWriter::new(std::fs::File::create(output)?))
+        };
+        let mut count = 0;
+        for location in &locations {
+            let events = event_builder::build_location_events_at(
+                location.lat,
+                location.lon,
+                location.accuracy,
+                location.timestamp,
+                &keys,
+                &config,
+            )?;
+            for event in events {
+                writeln!(out, "{}", event.as_json())?;
+                count += 1;
+            }
+        }
+        out.flush()?;
+        eprintln!("Wrote {} events to {}", count, output);
+        return Ok(());
+    }
+
+    let publisher = Publisher::connect_with(keys, config).await?;
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate));
+    let mut failed = 0;
+    for (fix, location) in &points {
+        ticks.tick().await;
+        if let Err(e) = publisher.publish_at(fix, location.timestamp).await {
+            let time = location.timestamp.unwrap_or_default().to_human_datetime();
+            eprintln!("Publishing the location of {} failed: {}", time, e);
+            failed += 1;
+        }
+    }
+    eprintln!("Published {} of {} locations", locations.len() - failed, locations.len());
+    Ok(())
+}
+
+/// Location records for `fixes`, each with its fix, and the number of fixes
+/// skipped for coordinates or an accuracy that can't be published.
+fn locations(fixes: Vec<Fix>, config: &TrackingConfig) -> (Vec<(Fix, LocationData)>, usize) {
+    let total = fixes.len();
+    let points: Vec<(Fix, LocationData)> = fixes
+        .into_iter()
+        .filter(|fix| fix.accuracy.is_none_or(|accuracy| accuracy.is_finite() && accuracy >= 0.0))
+        .filter_map(|fix| {
+            let mut location = fix.to_location(&config.d_tag, config.precision).ok()?;
+            location.encrypted = config.encrypted || config.gift_wrap;
+            Some((fix, location))
+        })
+        .collect();
+    let invalid = total - points.len();
+    (points, invalid)
+}
+
+/// Parse `text` into fixes with a time, and the number of points without one.
+fn parse(format: ImportFormat, text: &str) -> Result<(Vec<Fix>, usize)> {
+    let fixes = match format {
+        ImportFormat::Takeout => parse_takeout(text)?,
+        ImportFormat::Owntracks => parse_owntracks(text),
+        ImportFormat::Gpx => parse_gpx(text),
+    };
+    let total = fixes.len();
+    let dated: Vec<Fix> = fixes.into_iter().filter(|fix| fix.time.is_some()).collect();
+    let undated = total - dated.len();
+    Ok((dated, undated))
+}
+
+/// Google Takeout location history: `locations` from Records.json
+/// (`latitudeE7`/`longitudeE7`, `timestamp` or `timestampMs`), and
+/// `semanticSegments[].timelinePath` and `rawSignals[].position` from the
+/// on-device Timeline.json export.
+fn parse_takeout(text: &str) -> Result<Vec<Fix>> {
+    let json: Value = serde_json::from_str(text)?;
+    let mut fixes = Vec::new();
+
+    for record in json["locations"].as_array().into_iter().flatten() {
+        let (Some(lat), Some(lon)) = (record["latitudeE7"].as_i64(), record["longitudeE7"].as_i64()) else {
+            continue;
+        };
+        let time = match &record["timestamp"] {
+            Value::String(ts) => datetime::parse(ts),
+            _ => record["timestampMs"]
+                .as_str()
+                .and_then(|ms| ms.parse::<u64>().ok())
+                .map(|ms| Timestamp::from(ms / 1000)),
+        };
+        fixes.push(Fix {
+            lat: lat as f64 / 1e7,
+            lon: lon as f64 / 1e7,
+            accuracy: record["accuracy"].as_f64(),
+            speed: record["velocity"].as_f64(),
+            course: record["heading"].as_f64(),
+            time,
+        });
+    }
+
+    for segment in json["semanticSegments"].as_array().into_iter().flatten() {
+        for point in segment["timelinePath"].as_array().into_iter().flatten() {
+            if let Some((lat, lon)) = point["point"].as_str().and_then(parse_lat_lng) {
+                let mut fix = Fix::new(lat, lon, None);
+                fix.time = point["time"].as_str().and_then(datetime::parse);
+                fixes.push(fix);
+            }
+        }
+    }
+
+    for signal in json["rawSignals"].as_array().into_iter().flatten() {
+        let position = &signal["position"];
+        if let Some((lat, lon)) = position["LatLng"].as_str().and_then(parse_lat_lng) {
+            let mut fix = Fix::new(lat, lon, position["accuracyMeters"].as_f64());
+            fix.speed = position["speedMetersPerSecond"].as_f64();
+            fix.time = position["timestamp"].as_str().and_then(datetime::parse);
+            fixes.push(fix);
+        }
+    }
+    Ok(fixes)
+}
+
+/// `"60.1699°, 24.9384°"` (Timeline.json) or `"geo:60.1699,24.9384"`.
+fn parse_lat_lng(s: &str) -> Option<(f64, f64)> {
+    let s = s.strip_prefix("geo:").unwrap_or(s);
+    let (lat, lon) = s.split_once(',')?;
+    let number = |v: &str| v.trim().trim_end_matches('°').parse::<f64>().ok();
+    Some((number(lat)?, number(lon)?))
+}
+
+/// OwnTracks recorder `.rec` lines: `<time>\t<topic or *>\t<JSON payload>`.
+/// Only `_type: location` payloads are used.
+fn parse_owntracks(text: &str) -> Vec<Fix> {
+    let mut fixes = Vec::new();
+    for line in text.lines() {
+        let Some(start) = line.find('{') else { continue };
+        let Ok(payload) = serde_json::from_str::<Value>(&line[start..]) else { continue };
+        if payload["_type"] != "location" {
+            continue;
+        }
+        let (Some(lat), Some(lon)) = (payload["lat"].as_f64(), payload["lon"].as_f64()) else {
+            continue;
+        };
+        let mut fix = Fix::new(lat, lon, payload["acc"].as_f64());
+        // `vel` is km/h.
+        fix.speed = payload["vel"].as_f64().map(|kmh| kmh / 3.6);
+        fix.course = payload["cog"].as_f64();
+        fix.time = payload["tst"]
+            .as_u64()
+            .map(Timestamp::from)
+            .or_else(|| line.split('\t').next().and_then(datetime::parse));
+        fixes.push(fix);
+    }
+    fixes
+}
+
+/// Points of a GPX file: `trkpt`, `rtept` and `wpt` with their `time`;
+/// `hdop` becomes an accuracy estimate as for NMEA.
+fn parse_gpx(text: &str) -> Vec<Fix> {
+    let mut fixes = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let name_end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/').unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        if !matches!(name, "trkpt" | "rtept" | "wpt") {
+            continue;
+        }
+        let Some(tag_end) = rest.find('>') else { break };
+        let attributes = &rest[name_end..tag_end];
+        let body = if attributes.ends_with('/') {
+            ""
+        } else {
+            let close = format!("</{}>", name);
+            let body_end = rest.find(&close).unwrap_or(rest.len());
+            &rest[tag_end + 1..body_end]
+        };
+        rest = &rest[tag_end + 1..];
+
+        let (Some(lat), Some(lon)) = (
+            xml_attribute(attributes, "lat").and_then(|v| v.parse().ok()),
+            xml_attribute(attributes, "lon").and_then(|v| v.parse().ok()),
+        ) else {
+            continue;
+        };
+        let hdop: Option<f64> = xml_element(body, "hdop").and_then(|v| v.trim().parse().ok());
+        let mut fix = Fix::new(lat, lon, hdop.map(|hdop| hdop * UERE_M));
+        fix.time = xml_element(body, "time").and_then(datetime::parse);
+        fixes.push(fix);
+    }
+    fixes
+}
+
+fn xml_attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attributes;
+    while let Some(eq) = rest.find('=') {
+        let key = rest[..eq].trim();
+        let after = rest[eq + 1..].trim_start();
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value_end = after[1..].find(quote)? + 1;
+        if key == name {
+            return Some(&after[1..value_end]);
+        }
+        rest = &after[value_end + 1..];
+    }
+    None
+}
+
+fn xml_element<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{}>", name);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&format!("</{}>", name))?;
+    Some(&body[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> Option<Timestamp> {
+        datetime::parse(s)
+    }
+
+    #[test]
+    fn parses_takeout() {
+        let records = r#"{"locations": [
+            {"latitudeE7": 601699000, "longitudeE7": 249384000, "accuracy": 12, "timestamp": "2019-05-01T08:00:00.123Z"},
+            {"latitudeE7": -338600000, "longitudeE7": 1512100000, "timestampMs": "1556697600000", "velocity": 3},
+            {"latitudeE7": 1}
+        ]}"#;
+        let (fixes, undated) = parse(ImportFormat::Takeout, records).unwrap();
+        assert_eq!((fixes.len(), undated), (2, 0));
+        assert_eq!((fixes[0].lat, fixes[0].lon, fixes[0].accuracy), (60.1699, 24.9384, Some(12.0)));
+        assert_eq!(fixes[0].time, at("2019-05-01T08:00:00Z"));
+        assert_eq!((fixes[1].lat, fixes[1].time, fixes[1].speed), (-33.86, Some(Timestamp::from(1_556_697_600)), Some(3.0)));
+
+        let timeline = r#"{
+            "semanticSegments": [{"timelinePath": [{"point": "60.1699000°, 24.9384000°", "time": "2024-06-01T10:00:00.000+03:00"}]}],
+            "rawSignals": [{"position": {"LatLng": "60.17°, 24.94°", "accuracyMeters": 8, "timestamp": "2024-06-01T07:05:00Z"}}, {"wifiScan": {}}]
+        }"#;
+        let (fixes, _) = parse(ImportFormat::Takeout, timeline).unwrap();
+        assert_eq!(fixes.len(), 2);
+        assert_eq!((fixes[0].lat, fixes[0].time), (60.1699, at("2024-06-01T07:00:00Z")));
+        assert_eq!((fixes[1].lon, fixes[1].accuracy), (24.94, Some(8.0)));
+        assert!(parse(ImportFormat::Takeout, "[not json").is_err());
+    }
+
+    #[test]
+    fn parses_owntracks_and_gpx() {
+        let rec = concat!(
+            "2023-05-01T10:00:00Z\t*                 \t{\"_type\":\"location\",\"lat\":60.1699,\"lon\":24.9384,\"acc\":15,\"vel\":36,\"tst\":1682935200}\n",
+            "2023-05-01T10:00:05Z\tlwt               \t{\"_type\":\"lwt\",\"tst\":1682935205}\n",
+            "2023-05-01T10:01:00Z\t*                 \t{\"_type\":\"location\",\"lat\":60.17,\"lon\":24.94}\n",
+        );
+        let (fixes, _) = parse(ImportFormat::Owntracks, rec).unwrap();
+        assert_eq!(fixes.len(), 2);
+        assert_eq!((fixes[0].accuracy, fixes[0].speed), (Some(15.0), Some(10.0)));
+        assert_eq!(fixes[0].time, Some(Timestamp::from(1_682_935_200)));
+        assert_eq!(fixes[1].time, at("2023-05-01T10:01:00Z"));
+
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test"><metadata><time>2020-01-01T00:00:00Z</time></metadata>
+  <wpt lat='1.5' lon='2.5'/>
+  <trk><name>walk</name><trkseg>
+    <trkpt lat="60.1699" lon="24.9384"><ele>12</ele><time>2023-05-01T10:00:00Z</time><hdop>2</hdop></trkpt>
+    <trkpt
+        lon="24.9400" lat="60.1700"><time>2023-05-01T10:00:10+00:00</time></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+        let (fixes, undated) = parse(ImportFormat::Gpx, gpx).unwrap();
+        assert_eq!((fixes.len(), undated), (2, 1));
+        assert_eq!((fixes[0].lat, fixes[0].accuracy), (60.1699, Some(2.0 * UERE_M)));
+        assert_eq!((fixes[1].lat, fixes[1].lon), (60.17, 24.94));
+        assert_eq!(fixes[1].time, at("2023-05-01T10:00:10Z"));
+    }
+
+    #[test]
+    fn skips_invalid_points() {
+        let fixes = vec![
+            Fix::new(60.17, 24.94, Some(5.0)),
+            Fix::new(91.0, 24.94, None),
+            Fix::new(60.17, f64::NAN, None),
+            Fix::new(60.17, 24.94, Some(-1.0)),
+            Fix::new(-33.86, 151.21, None),
+        ];
+        let config = TrackingConfig { encrypted: true, ..TrackingConfig::default() };
+        let (points, invalid) = locations(fixes, &config);
+        assert_eq!((points.len(), invalid), (2, 3));
+        assert_eq!(points[1].0.lat, -33.86);
+        assert!(points[0].1.encrypted);
+    }
+}
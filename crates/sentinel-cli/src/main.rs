@@ -1,11 +1,25 @@
 use clap::{Parser, Subcommand};
 use nostr_sdk::prelude::*;
-use sentinel_core::event_parser;
-use std::sync::{Arc, Mutex};
+use sentinel_core::event_parser::{self, ParsedLocation};
+use sentinel_core::geofence::{self, Geofence, GeofenceTracker};
+use sentinel_core::{event_builder, kinds};
 use std::time::Duration;
 
+mod alert;
+mod datetime;
+mod export;
+mod follow;
+mod identity;
+mod import;
+mod merge;
+mod output;
+mod publish;
+mod relay_auth;
+mod relay_health;
+mod source;
+
 #[derive(Parser)]
-#[command(name = "sentinel", about = "Query Nostr location events")]
+#[command(name = "sentinel", about = "Query and publish Nostr location events")]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -27,34 +41,258 @@ enum Commands {
         #[arg(long)]
         d_tag: Option<String>,
 
-        /// nsec to decrypt kind 30473 events
+        /// nsec to decrypt kind 30473 and unwrap gift-wrapped (kind 1059) events
+        /// (default: the stored identity)
         #[arg(long)]
         decrypt_with: Option<String>,
+
+        /// Stored identity to decrypt with instead of the default
+        #[arg(long, conflicts_with = "decrypt_with")]
+        identity: Option<String>,
+
+        /// Oldest events to fetch, as a duration ago ("2h", "7d") or a date
+        /// ("2026-10-01", "2026-10-01T12:00:00Z")
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Newest events to fetch, as a duration ago or a date (default: now)
+        #[arg(long)]
+        until: Option<String>,
+
+        /// Events per relay request; full pages of history events are
+        /// followed back to --since
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+
+        /// How long to wait for each relay request
+        #[arg(long, default_value = "10s")]
+        timeout: String,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = output::Format::Table)]
+        format: output::Format,
+
+        /// Report relays that are stale or missing the latest location
+        #[arg(long, short)]
+        verbose: bool,
     },
 
-    /// Follow a pubkey and alert on missing updates
+    /// Follow pubkeys/devices and alert on missing updates
     Follow {
+        /// Public key to follow, any d-tag (hex or npub, repeatable)
+        #[arg(long)]
+        pubkey: Vec<String>,
+
+        /// Device to follow: "[name=]<pubkey>[:<d_tag>][@<alert_after>]" (repeatable)
+        #[arg(long)]
+        target: Vec<String>,
+
+        /// JSON file listing targets: [{"name", "pubkey", "d_tag", "alert_after"}]
+        #[arg(long)]
+        roster: Option<String>,
+
+        /// Default: alert if no update within this duration (e.g. "5m", "1h")
+        #[arg(long)]
+        alert_after: Option<String>,
+
+        /// Cap for the doubling interval between repeated silence alerts
+        #[arg(long, default_value = "6h")]
+        alert_repeat_max: String,
+
+        /// Report a relay as unreachable after it has been disconnected this long
+        #[arg(long, default_value = "1m")]
+        relay_grace: String,
+
+        /// Drop silence alerts when no relay was connected while the target was silent
+        /// (by default they are sent marked as degraded)
+        #[arg(long)]
+        suppress_degraded: bool,
+
+        #[command(flatten)]
+        alerts: Box<alert::AlertArgs>,
+
+        /// Relay URLs
+        #[arg(long, default_value = "wss://zooid.atlantislabs.space")]
+        relays: Vec<String>,
+
+        /// nsec to decrypt kind 30473 and unwrap gift-wrapped (kind 1059) events
+        /// (default: the stored identity)
+        #[arg(long)]
+        decrypt_with: Option<String>,
+
+        /// Stored identity to decrypt with instead of the default
+        #[arg(long, conflicts_with = "decrypt_with")]
+        identity: Option<String>,
+
+        /// Alert on enter/exit/dwell, e.g. "home=circle:60.17,24.94,200"
+        #[arg(long)]
+        geofence: Vec<String>,
+
+        /// JSON file with a list of geofence zones
+        #[arg(long)]
+        zones: Option<String>,
+    },
+
+    /// Fetch history-mode location events and print them as an ordered track
+    History {
         /// Public key (hex or npub)
         #[arg(long)]
         pubkey: String,
 
-        /// Alert if no update within this duration (e.g. "5m", "1h")
-        #[arg(long)]
-        alert_after: String,
+        /// Start of the track, as a duration ago or a date (e.g. "24h", "2026-10-01")
+        #[arg(long, default_value = "24h")]
+        since: String,
 
-        /// Webhook URL to POST alerts to
+        /// End of the track, as a duration ago or a date (default: now)
         #[arg(long)]
-        webhook: Option<String>,
+        until: Option<String>,
+
+        /// Events per relay request; full pages are followed back to --since
+        #[arg(long, default_value_t = 500)]
+        limit: usize,
+
+        /// How long to wait for each relay request
+        #[arg(long, default_value = "10s")]
+        timeout: String,
 
         /// Relay URLs
         #[arg(long, default_value = "wss://zooid.atlantislabs.space")]
         relays: Vec<String>,
+
+        /// Filter by d-tag
+        #[arg(long)]
+        d_tag: Option<String>,
+
+        /// nsec to decrypt kind 1473 and unwrap gift-wrapped (kind 1059) events
+        /// (default: the stored identity)
+        #[arg(long)]
+        decrypt_with: Option<String>,
+
+        /// Stored identity to decrypt with instead of the default
+        #[arg(long, conflicts_with = "decrypt_with")]
+        identity: Option<String>,
+
+        /// Report enter/exit/dwell along the track, e.g. "home=circle:60.17,24.94,200"
+        #[arg(long)]
+        geofence: Vec<String>,
+
+        /// JSON file with a list of geofence zones
+        #[arg(long)]
+        zones: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = output::Format::Table)]
+        format: output::Format,
+    },
+
+    /// Publish one location, signed with the stored identity
+    Publish {
+        /// Latitude
+        #[arg(long, allow_negative_numbers = true)]
+        lat: f64,
+
+        /// Longitude
+        #[arg(long, allow_negative_numbers = true)]
+        lon: f64,
+
+        /// Accuracy in meters
+        #[arg(long)]
+        accuracy: Option<f64>,
+
+        #[command(flatten)]
+        publish: publish::PublishArgs,
+    },
+
+    /// Publish the location from a source on an interval
+    Track {
+        /// Time between readings of the source
+        #[arg(long, default_value = "60s")]
+        interval: String,
+
+        #[command(flatten)]
+        source: source::SourceArgs,
+
+        #[command(flatten)]
+        policy: publish::PolicyArgs,
+
+        #[command(flatten)]
+        publish: publish::PublishArgs,
     },
 
-    /// Show configured identity
+    /// Convert an NMEA 0183 log to location records (one JSON object per line)
+    Nmea {
+        /// Log file, or "-" for stdin
+        input: String,
+
+        /// Device identifier (`d` tag) for the records
+        #[arg(long, default_value = "default")]
+        d_tag: String,
+
+        /// Geohash precision (1-12)
+        #[arg(long, default_value_t = 8)]
+        precision: u8,
+    },
+
+    /// Write a location track as GPX, GeoJSON or KML
+    Export {
+        #[command(flatten)]
+        args: export::ExportArgs,
+    },
+
+    /// Turn Google Takeout, OwnTracks or GPX history into history events
+    Import {
+        #[command(flatten)]
+        args: import::ImportArgs,
+    },
+
+    /// Manage stored identities (NIP-49 encrypted keys)
+    Identity {
+        #[command(subcommand)]
+        command: IdentityCommand,
+    },
+
+    /// Show the default identity
     Whoami,
 }
 
+#[derive(Subcommand)]
+enum IdentityCommand {
+    /// Generate a new key pair and store it
+    Generate {
+        /// Name for the identity
+        name: String,
+    },
+
+    /// Store an existing key
+    Import {
+        /// Name for the identity
+        name: String,
+
+        /// nsec, hex or ncryptsec (read from stdin when omitted, keeping it
+        /// out of shell history)
+        key: Option<String>,
+    },
+
+    /// Print an identity's ncryptsec
+    Export {
+        /// Identity name (default: the default identity)
+        name: Option<String>,
+
+        /// Decrypt and print the nsec instead
+        #[arg(long)]
+        nsec: bool,
+    },
+
+    /// List stored identities
+    List,
+
+    /// Make an identity the default
+    Use {
+        /// Identity name
+        name: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -65,19 +303,106 @@ async fn main() -> Result<()> {
             relays,
             d_tag,
             decrypt_with,
+            identity,
+            since,
+            until,
+            limit,
+            timeout,
+            format,
+            verbose,
         } => {
-            cmd_query(&pubkey, &relays, d_tag.as_deref(), decrypt_with.as_deref()).await?;
+            let keys = load_keys(decrypt_with.as_deref(), identity.as_deref())?;
+            let window = QueryWindow {
+                since: since.as_deref().map(parse_time).transpose()?,
+                until: until.as_deref().map(parse_time).transpose()?,
+                limit,
+                timeout: parse_duration_str(&timeout)?,
+            };
+            cmd_query(&pubkey, &relays, d_tag.as_deref(), keys, window, format, verbose).await?;
         }
         Commands::Follow {
             pubkey,
+            target,
+            roster,
             alert_after,
-            webhook,
+            alert_repeat_max,
+            relay_grace,
+            suppress_degraded,
+            alerts,
             relays,
+            decrypt_with,
+            identity,
+            geofence,
+            zones,
+        } => {
+            let targets =
+                follow::load_targets(&pubkey, &target, roster.as_deref(), alert_after.as_deref())?;
+            let policy = follow::AlertPolicy {
+                repeat_max: parse_duration_str(&alert_repeat_max)?,
+                relay_grace: parse_duration_str(&relay_grace)?,
+                suppress_degraded,
+            };
+            // nostr-dm alerts are signed, so they need the identity unlocked.
+            let keys = match decrypt_with.as_deref() {
+                None if alerts.signs() => Some(signing_keys(identity.as_deref())?),
+                decrypt_with => load_keys(decrypt_with, identity.as_deref())?,
+            };
+            let zones = load_zones(&geofence, zones.as_deref())?;
+
+            let alerter = alerts.alerter(&relays, keys.as_ref()).await?;
+            follow::cmd_follow(targets, alerter, &relays, keys, zones, policy).await?;
+        }
+        Commands::History {
+            pubkey,
+            since,
+            until,
+            limit,
+            timeout,
+            relays,
+            d_tag,
+            decrypt_with,
+            identity,
+            geofence,
+            zones,
+            format,
         } => {
-            cmd_follow(&pubkey, &alert_after, webhook.as_deref(), &relays).await?;
+            let keys = load_keys(decrypt_with.as_deref(), identity.as_deref())?;
+            let zones = load_zones(&geofence, zones.as_deref())?;
+            let window = QueryWindow {
+                since: Some(parse_time(&since)?),
+                until: Some(until.as_deref().map(parse_time).transpose()?.unwrap_or_else(Timestamp::now)),
+                limit,
+                timeout: parse_duration_str(&timeout)?,
+            };
+            cmd_history(&pubkey, window, &relays, d_tag.as_deref(), keys, zones, format).await?;
+        }
+        Commands::Publish { lat, lon, accuracy, publish } => {
+            let fix = source::Fix::new(lat, lon, accuracy);
+            publish::cmd_publish(fix, &publish).await?;
         }
+        Commands::Track { interval, source, policy, publish } => {
+            let interval = parse_duration_str(&interval)?;
+            publish::cmd_track(source.source().await?, interval, &publish, &policy).await?;
+        }
+        Commands::Nmea { input, d_tag, precision } => {
+            let fixes = source::nmea::read_fixes(source::nmea::open(&input).await?).await?;
+            for fix in &fixes {
+                println!("{}", serde_json::to_string(&fix.to_location(&d_tag, precision)?)?);
+            }
+            eprintln!("{} fixes", fixes.len());
+        }
+        Commands::Export { args } => export::cmd_export(args).await?,
+        Commands::Import { args } => import::cmd_import(args).await?,
+        Commands::Identity { command } => cmd_identity(command)?,
         Commands::Whoami => {
-            eprintln!("No identity configured (CLI uses --decrypt-with for decryption)");
+            let store = identity::Store::open()?;
+            match store.get(None)? {
+                Some(identity) => {
+                    println!("{}", identity.npub);
+                    eprintln!("Identity '{}' ({})", identity.name, store.path().display());
+                }
+                None => eprintln!("No identity configured (create one with `sentinel identity generate <name>`)"),
+            }
         }
     }
 
@@ -92,14 +417,144 @@ fn parse_pubkey(input: &str) -> Result<PublicKey> {
     }
 }
 
+fn parse_secret_keys(input: &str) -> Result<Keys> {
+    if input.starts_with("nsec") {
+        Ok(Keys::parse(input)?)
+    } else {
+        Ok(Keys::new(SecretKey::from_hex(input)?))
+    }
+}
+
+/// Keys to decrypt with: `--decrypt-with`, else the `--identity` or default
+/// identity. Public locations need no key, so if the default identity can't be
+/// unlocked (no terminal to ask for its password and no `SENTINEL_PASSWORD`,
+/// a wrong password, no config directory) this warns and returns `None`.
+fn load_keys(decrypt_with: Option<&str>, identity: Option<&str>) -> Result<Option<Keys>> {
+    if let Some(secret) = decrypt_with {
+        return Ok(Some(parse_secret_keys(secret)?));
+    }
+    if identity.is_some() {
+        return Ok(Some(signing_keys(identity)?));
+    }
+    let unlocked = (|| -> Result<Option<Keys>> {
+        let Some(stored) = identity::Store::open()?.get(None)? else {
+            return Ok(None);
+        };
+        if !identity::password_available() {
+            return Err(format!(
+                "identity '{}' is locked; no terminal to ask for its password and {} is not set",
+                stored.name,
+                identity::PASSWORD_ENV
+            )
+            .into());
+        }
+        let password = identity::read_password(&format!("Password for identity '{}': ", stored.name), false)?;
+        Ok(Some(stored.unlock(&password)?))
+    })();
+    Ok(unlocked.unwrap_or_else(|e| {
+        eprintln!("Warning: not decrypting: {}", e);
+        None
+    }))
+}
+
+/// Keys that must be there, to sign with: `--identity` or the default
+/// identity. Failing to unlock it is an error.
+fn signing_keys(identity: Option<&str>) -> Result<Keys> {
+    let stored = identity::Store::open()?
+        .get(identity)?
+        .ok_or("No identity to sign with (create one with `sentinel identity generate <name>`)")?;
+    let password = identity::read_password(&format!("Password for identity '{}': ", stored.name), false)?;
+    Ok(stored.unlock(&password)?)
+}
+
+fn cmd_identity(command: IdentityCommand) -> Result<()> {
+    let store = identity::Store::open()?;
+    match command {
+        IdentityCommand::Generate { name } => {
+            let password = identity::read_password("New password: ", true)?;
+            let identity = store.add(&name, &Keys::generate(), &password, KeySecurity::Medium)?;
+            println!("{}", identity.npub);
+            eprintln!("Stored identity '{}' in {}", identity.name, store.path().display());
+        }
+        IdentityCommand::Import { name, key } => {
+            let key = match key {
+                Some(key) => key,
+                None => identity::read_secret("Key (nsec, hex or ncryptsec): ")?,
+            };
+            let key = key.trim();
+            let identity = if key.starts_with("ncryptsec") {
+                let password = identity::read_password("Password for the ncryptsec: ", false)?;
+                store.add_encrypted(&name, key, &password)?
+            } else {
+                let keys = parse_secret_keys(key)?;
+                let password = identity::read_password("New password: ", true)?;
+                // The key has been handled unencrypted.
+                store.add(&name, &keys, &password, KeySecurity::Weak)?
+            };
+            println!("{}", identity.npub);
+            eprintln!("Stored identity '{}' in {}", identity.name, store.path().display());
+        }
+        IdentityCommand::Export { name, nsec } => {
+            let identity = store.get(name.as_deref())?.ok_or("No identity configured")?;
+            if nsec {
+                let password =
+                    identity::read_password(&format!("Password for identity '{}': ", identity.name), false)?;
+                println!("{}", identity.unlock(&password)?.secret_key().to_bech32()?);
+            } else {
+                println!("{}", identity.ncryptsec);
+            }
+        }
+        IdentityCommand::List => {
+            let (identities, default) = store.list()?;
+            if identities.is_empty() {
+                eprintln!("No identities in {}", store.path().display());
+            }
+            for identity in identities {
+                let marker = if default.as_deref() == Some(identity.name.as_str()) { "*" } else { " " };
+                println!("{} {:<16} {}", marker, identity.name, identity.npub);
+            }
+        }
+        IdentityCommand::Use { name } => {
+            store.set_default(&name)?;
+            eprintln!("Default identity is now '{}'", name);
+        }
+    }
+    Ok(())
+}
+
+/// Collect zones from repeated `--geofence` specs and an optional JSON file.
+fn load_zones(specs: &[String], file: Option<&str>) -> Result<Vec<Geofence>> {
+    let mut zones = match file {
+        Some(path) => geofence::parse_zones(&std::fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    for spec in specs {
+        zones.push(spec.parse()?);
+    }
+    Ok(zones)
+}
+
+/// Parse a time given as a duration ago ("24h") or as a date or RFC 3339
+/// time ("2026-10-01", "2026-10-01T12:00:00Z").
+fn parse_time(s: &str) -> Result<Timestamp> {
+    if let Some(time) = datetime::parse(s) {
+        return Ok(time);
+    }
+    let ago = parse_duration_str(s)
+        .map_err(|_| format!("Invalid time: {} (use a duration ago like 2h or a date like 2026-10-01)", s))?;
+    Ok(Timestamp::now() - ago)
+}
+
 fn parse_duration_str(s: &str) -> std::result::Result<Duration, String> {
     let s = s.trim();
-    let (num_str, multiplier) = if s.ends_with('h') {
-        (&s[..s.len() - 1], 3600u64)
-    } else if s.ends_with('m') {
-        (&s[..s.len() - 1], 60u64)
-    } else if s.ends_with('s') {
-        (&s[..s.len() - 1], 1u64)
+    let (num_str, multiplier) = if let Some(n) = s.strip_suffix('d') {
+        (n, 86_400u64)
+    } else if let Some(n) = s.strip_suffix('h') {
+        (n, 3600u64)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60u64)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1u64)
     } else {
         (s, 1u64)
     };
@@ -108,182 +563,300 @@ fn parse_duration_str(s: &str) -> std::result::Result<Duration, String> {
     Ok(Duration::from_secs(num * multiplier))
 }
 
+/// Which events `query`, `history` and `export` ask relays for.
+struct QueryWindow {
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+    /// Page size of each relay request.
+    limit: usize,
+    timeout: Duration,
+}
+
+impl QueryWindow {
+    fn check(&self) -> Result<()> {
+        match (self.since, self.until) {
+            (Some(since), Some(until)) if since > until => Err("--since is after --until".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
 async fn cmd_query(
     pubkey_str: &str,
     relays: &[String],
     d_tag: Option<&str>,
-    decrypt_with: Option<&str>,
+    decrypt_keys: Option<Keys>,
+    window: QueryWindow,
+    format: output::Format,
+    verbose: bool,
 ) -> Result<()> {
+    window.check()?;
     let pubkey = parse_pubkey(pubkey_str)?;
-    let client = Client::default();
+    let (client, auth) = relay_auth::client(decrypt_keys.as_ref());
 
     for relay in relays {
         client.add_relay(relay).await?;
     }
     client.connect().await;
 
-    let mut filter = Filter::new()
-        .author(pubkey)
-        .kinds(vec![Kind::from(30472), Kind::from(30473)])
-        .limit(20);
+    let mut filter = Filter::new().author(pubkey);
+    if let Some(since) = window.since {
+        filter = filter.since(since);
+    }
+    if let Some(until) = window.until {
+        filter = filter.until(until);
+    }
 
     if let Some(d) = d_tag {
-        filter = filter.identifier(d);
+        let mut identifiers = vec![d.to_string()];
+        if let Some(ref keys) = decrypt_keys {
+            identifiers.push(event_builder::recipient_d_tag(d, &keys.public_key()));
+        }
+        filter = filter.identifiers(identifiers);
     }
 
-    let events = client
-        .fetch_events(vec![filter], Some(Duration::from_secs(10)))
-        .await?;
-
-    let decrypt_keys = match decrypt_with {
-        Some(nsec) => {
-            let keys = if nsec.starts_with("nsec") {
-                Keys::parse(nsec)?
-            } else {
-                Keys::new(SecretKey::from_hex(nsec)?)
-            };
-            Some(keys)
+    // Latest locations are one per address, so they are fetched whole: a
+    // relay without one of them is then known to be missing it. History is
+    // paged.
+    let latest = filter
+        .clone()
+        .kinds([Kind::from(kinds::PUBLIC_LOCATION), Kind::from(kinds::ENCRYPTED_LOCATION)]);
+    let history = filter
+        .kinds([
+            Kind::from(kinds::PUBLIC_LOCATION_HISTORY),
+            Kind::from(kinds::ENCRYPTED_LOCATION_HISTORY),
+        ])
+        .limit(window.limit);
+    let mut filters = vec![latest, history];
+    if let Some(ref keys) = decrypt_keys {
+        // Gift wraps hide the author, so fetch everything addressed to us.
+        // Wrap timestamps are at most GIFT_WRAP_TIMESTAMP_TWEAK_SECS before the rumor's.
+        let mut wraps = Filter::new().kind(Kind::GiftWrap).pubkey(keys.public_key()).limit(window.limit);
+        if let Some(since) = window.since {
+            wraps = wraps.since(since - event_builder::GIFT_WRAP_TIMESTAMP_TWEAK_SECS);
+        }
+        if let Some(until) = window.until {
+            wraps = wraps.until(until);
         }
-        None => None,
+        filters.push(wraps);
+    }
+
+    // Gift wraps may hold history too; only decrypting them would tell.
+    let is_history = |event: &Event| {
+        matches!(
+            event.kind.as_u16(),
+            kinds::PUBLIC_LOCATION_HISTORY | kinds::ENCRYPTED_LOCATION_HISTORY | 1059
+        )
     };
+    let results = relay_auth::fetch_pages_by_relay(&client, &auth, filters, window.timeout, is_history).await;
+    if results.iter().all(|(_, result)| result.is_err()) {
+        if let Some((_, Err(e))) = results.into_iter().next() {
+            return Err(e.into());
+        }
+        return Err("No relays to query".into());
+    }
 
-    for event in events.iter() {
-        match event.kind.as_u16() {
-            30472 => {
-                if let Ok(loc) = event_parser::parse_public_event(event) {
-                    println!(
-                        "[{}] kind:{} d:{} geohash:{} lat:{:.6} lon:{:.6} acc:{:?}",
-                        loc.timestamp.to_human_datetime(),
-                        loc.kind,
-                        loc.d_tag,
-                        loc.geohash,
-                        loc.lat,
-                        loc.lon,
-                        loc.accuracy,
-                    );
-                }
+    let mut answers = Vec::new();
+    for (relay, result) in results {
+        match result {
+            Ok(events) => answers.push((relay, events)),
+            Err(e) => eprintln!("Fetching from {} failed: {}", relay, e),
+        }
+    }
+    let merged = merge::merge(&answers);
+    if verbose {
+        for conflict in &merged.conflicts {
+            eprintln!("{}", conflict);
+        }
+        eprintln!(
+            "{} of {} locations are missing or stale on some of the {} relays that answered",
+            merged.conflicts.len(),
+            merged.addresses,
+            answers.len()
+        );
+    }
+
+    let mut records = Vec::new();
+    for (relay, event) in &merged.events {
+        let record = match read_location(event, &[pubkey], decrypt_keys.as_ref()) {
+            Some(Ok(loc)) => output::Record::location(event, relay, loc),
+            Some(Err(e @ sentinel_core::Error::Decryption(_))) => output::Record::unreadable(
+                event,
+                relay,
+                output::Status::DecryptFailed,
+                Some(e.to_string()),
+            ),
+            Some(Err(e)) => {
+                output::Record::unreadable(event, relay, output::Status::Invalid, Some(e.to_string()))
             }
-            30473 => {
-                if let Some(ref keys) = decrypt_keys {
-                    match nip44::decrypt(
-                        keys.secret_key(),
-                        &event.pubkey,
-                        &event.content,
-                    ) {
-                        Ok(decrypted) => {
-                            if let Ok(loc) =
-                                event_parser::parse_encrypted_content(event, &decrypted)
-                            {
-                                println!(
-                                    "[{}] kind:{} d:{} geohash:{} lat:{:.6} lon:{:.6} acc:{:?} (decrypted)",
-                                    loc.timestamp.to_human_datetime(),
-                                    loc.kind,
-                                    loc.d_tag,
-                                    loc.geohash,
-                                    loc.lat,
-                                    loc.lon,
-                                    loc.accuracy,
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to decrypt event {}: {}", event.id, e);
-                        }
-                    }
-                } else {
-                    println!(
-                        "[{}] kind:30473 (encrypted, use --decrypt-with to decode)",
-                        event.created_at.to_human_datetime(),
-                    );
-                }
+            None if matches!(
+                event.kind.as_u16(),
+                kinds::ENCRYPTED_LOCATION | kinds::ENCRYPTED_LOCATION_HISTORY
+            ) =>
+            {
+                output::Record::unreadable(event, relay, output::Status::Encrypted, None)
             }
-            _ => {}
+            None => continue,
+        };
+        let in_window = window.since.is_none_or(|since| record.timestamp >= since.as_u64())
+            && window.until.is_none_or(|until| record.timestamp <= until.as_u64());
+        if in_window && d_tag.is_none_or(|d| d == record.d_tag) {
+            records.push(record);
         }
     }
+    records.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then_with(|| a.event_id.cmp(&b.event_id)));
+    output::write(&mut std::io::stdout().lock(), format, &records)?;
 
-    client.disconnect().await;
+    client.disconnect().await?;
     Ok(())
 }
 
-async fn cmd_follow(
+async fn cmd_history(
     pubkey_str: &str,
-    alert_after_str: &str,
-    webhook: Option<&str>,
+    window: QueryWindow,
     relays: &[String],
+    d_tag: Option<&str>,
+    decrypt_keys: Option<Keys>,
+    zones: Vec<Geofence>,
+    format: output::Format,
 ) -> Result<()> {
     let pubkey = parse_pubkey(pubkey_str)?;
-    let alert_duration = parse_duration_str(alert_after_str)
-        .expect("Invalid duration format (use e.g. 5m, 1h, 30s)");
+    let track = fetch_track(pubkey, &window, relays, d_tag, decrypt_keys.as_ref()).await?;
+
+    let mut tracker = GeofenceTracker::new(zones);
+    let mut records = Vec::new();
+    for (relay, event, loc) in track {
+        // Transitions go to stderr so stdout stays one `--format` document.
+        for transition in tracker.update(&loc)? {
+            eprintln!(
+                "[{}] {} zone:{}",
+                loc.timestamp.to_human_datetime(),
+                transition.kind.as_str(),
+                transition.zone,
+            );
+        }
+        records.push(output::Record::location(&event, &relay, loc));
+    }
+    output::write(&mut std::io::stdout().lock(), format, &records)?;
+    eprintln!(
+        "{} points between {} and {}",
+        records.len(),
+        window.since.unwrap_or_default().to_human_datetime(),
+        window.until.unwrap_or_else(Timestamp::now).to_human_datetime(),
+    );
+    Ok(())
+}
 
-    let client = Client::default();
+/// Fetch the history-mode track of `pubkey` in `window`, paging back through
+/// each relay's answers, decrypting with `decrypt_keys`, in timestamp order.
+async fn fetch_track(
+    pubkey: PublicKey,
+    window: &QueryWindow,
+    relays: &[String],
+    d_tag: Option<&str>,
+    decrypt_keys: Option<&Keys>,
+) -> Result<Vec<(RelayUrl, Event, ParsedLocation)>> {
+    window.check()?;
+    let (client, auth) = relay_auth::client(decrypt_keys);
     for relay in relays {
         client.add_relay(relay).await?;
     }
     client.connect().await;
 
-    let filter = Filter::new()
+    let mut filter = Filter::new()
         .author(pubkey)
-        .kinds(vec![Kind::from(30472), Kind::from(30473)])
-        .since(Timestamp::now());
+        .kinds(vec![
+            Kind::from(kinds::PUBLIC_LOCATION_HISTORY),
+            Kind::from(kinds::ENCRYPTED_LOCATION_HISTORY),
+        ])
+        .limit(window.limit);
+    if let Some(since) = window.since {
+        filter = filter.since(since);
+    }
+    if let Some(until) = window.until {
+        filter = filter.until(until);
+    }
 
-    client.subscribe(vec![filter], None).await?;
+    if let Some(d) = d_tag {
+        let mut identifiers = vec![d.to_string()];
+        if let Some(keys) = decrypt_keys {
+            identifiers.push(event_builder::recipient_d_tag(d, &keys.public_key()));
+        }
+        filter = filter.identifiers(identifiers);
+    }
 
-    eprintln!(
-        "Following {} — alert after {:?} of silence",
-        pubkey.to_bech32()?,
-        alert_duration,
-    );
+    let mut filters = vec![filter];
+    if let Some(keys) = decrypt_keys {
+        // Wrap timestamps are at most GIFT_WRAP_TIMESTAMP_TWEAK_SECS before the rumor's.
+        let mut wraps = Filter::new().kind(Kind::GiftWrap).pubkey(keys.public_key()).limit(window.limit);
+        if let Some(since) = window.since {
+            wraps = wraps.since(since - event_builder::GIFT_WRAP_TIMESTAMP_TWEAK_SECS);
+        }
+        if let Some(until) = window.until {
+            wraps = wraps.until(until);
+        }
+        filters.push(wraps);
+    }
 
-    let last_event_time = Arc::new(Mutex::new(std::time::Instant::now()));
-    let last_event_clone = Arc::clone(&last_event_time);
-    let webhook_url = webhook.map(|s| s.to_string());
-    let alert_dur = alert_duration;
-
-    // Spawn alert checker
-    let pubkey_bech32 = pubkey.to_bech32()?;
-    tokio::spawn(async move {
-        loop {
-            tokio::time::sleep(Duration::from_secs(10)).await;
-            let elapsed = last_event_clone.lock().unwrap().elapsed();
-            if elapsed >= alert_dur {
-                let msg = format!(
-                    "ALERT: No location update from {} for {:?}",
-                    pubkey_bech32, alert_dur,
-                );
-                eprintln!("{}", msg);
-
-                if let Some(ref url) = webhook_url {
-                    let body = serde_json::json!({ "text": msg });
-                    let _ = reqwest::Client::new()
-                        .post(url)
-                        .json(&body)
-                        .send()
-                        .await;
-                }
+    // Every event asked for is history or may hold it, so full pages are followed.
+    let events = relay_auth::fetch_pages(&client, &auth, filters, window.timeout, |_| true).await?;
 
-                // Reset timer
-                *last_event_clone.lock().unwrap() = std::time::Instant::now();
-            }
+    let mut track = Vec::new();
+    for (relay, event) in events {
+        match read_location(&event, &[pubkey], decrypt_keys) {
+            Some(Ok(loc)) => track.push((relay, event, loc)),
+            Some(Err(e)) => eprintln!("Skipping event {}: {}", event.id, e),
+            None => {}
         }
+    }
+
+    track.retain(|(_, _, loc)| {
+        window.since.is_none_or(|since| loc.timestamp >= since)
+            && window.until.is_none_or(|until| loc.timestamp <= until)
+            && d_tag.is_none_or(|d| d == loc.d_tag)
     });
+    track.sort_by_key(|(_, _, loc)| loc.timestamp);
 
-    // Process notifications
-    client
-        .handle_notifications(|notification| {
-            let last_event_time = Arc::clone(&last_event_time);
-            async move {
-                if let RelayPoolNotification::Event { event, .. } = notification {
-                    let kind = event.kind.as_u16();
-                    if kind == 30472 || kind == 30473 {
-                        eprintln!("Location update received (kind {})", kind);
-                        *last_event_time.lock().unwrap() = std::time::Instant::now();
-                    }
-                }
-                Ok(false) // false = don't stop
-            }
-        })
-        .await?;
+    client.disconnect().await?;
+    Ok(track)
+}
 
-    client.disconnect().await;
-    Ok(())
+/// All location kinds published by trackers, addressable and history.
+fn location_kinds() -> Vec<Kind> {
+    vec![
+        Kind::from(kinds::PUBLIC_LOCATION),
+        Kind::from(kinds::ENCRYPTED_LOCATION),
+        Kind::from(kinds::PUBLIC_LOCATION_HISTORY),
+        Kind::from(kinds::ENCRYPTED_LOCATION_HISTORY),
+    ]
+}
+
+/// Parse a location event by one of `authors` (any author if empty),
+/// decrypting or unwrapping with `keys`.
+///
+/// Returns `None` for events that carry no readable location: encrypted
+/// events without `keys`, and gift wraps from someone else or holding
+/// something other than a location (e.g. DMs).
+fn read_location(
+    event: &Event,
+    authors: &[PublicKey],
+    keys: Option<&Keys>,
+) -> Option<sentinel_core::Result<ParsedLocation>> {
+    match event.kind.as_u16() {
+        kinds::PUBLIC_LOCATION | kinds::PUBLIC_LOCATION_HISTORY => {
+            Some(event_parser::parse_public_event(event))
+        }
+        kinds::ENCRYPTED_LOCATION | kinds::ENCRYPTED_LOCATION_HISTORY => {
+            keys.map(|keys| event_parser::decrypt_and_parse(event, keys))
+        }
+        1059 => match event_parser::unwrap_gift_wrap(event, keys?) {
+            Ok(loc) if authors.is_empty() || authors.iter().any(|a| a.to_hex() == loc.pubkey) => {
+                Some(Ok(loc))
+            }
+            Ok(_) | Err(sentinel_core::Error::WrongKind { .. }) => None,
+            Err(e) => Some(Err(e)),
+        },
+        _ => None,
+    }
 }
+
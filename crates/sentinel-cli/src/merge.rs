@@ -0,0 +1,180 @@
+//! Merging the answers of several relays to one query. Replaceable and
+//! addressable events are grouped by address (kind, pubkey, `d` tag) and only
+//! the newest version is kept, ties going to the lowest event id (NIP-01).
+
+use nostr_sdk::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+/// Distinct events from all relays, and the addresses they disagree on.
+#[derive(Debug, Clone)]
+pub struct Merged {
+    /// Each event with the first relay (by answer order) that returned it.
+    pub events: Vec<(RelayUrl, Event)>,
+    /// Number of distinct addresses among the events.
+    pub addresses: usize,
+    pub conflicts: Vec<Conflict>,
+}
+
+/// An address for which some relays did not return the latest version.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// `kind:pubkey:d`, as in NIP-01 `a` tags.
+    pub address: String,
+    pub latest: EventId,
+    pub latest_at: Timestamp,
+    /// Relays that returned an older (or losing) version, with its time.
+    pub stale: Vec<(RelayUrl, Timestamp)>,
+    /// Relays that answered without any version of the address.
+    pub missing: Vec<RelayUrl>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: latest {} at {}", self.address, self.latest, self.latest_at.to_human_datetime())?;
+        for (relay, at) in &self.stale {
+            write!(f, "; {} is stale ({})", relay, at.to_human_datetime())?;
+        }
+        for relay in &self.missing {
+            write!(f, "; {} is missing it", relay)?;
+        }
+        Ok(())
+    }
+}
+
+type Address = (Kind, PublicKey, String);
+
+fn address(event: &Event) -> Option<Address> {
+    if event.kind.is_parameterized_replaceable() {
+        let d = event.tags.identifier().unwrap_or_default().to_string();
+        Some((event.kind, event.pubkey, d))
+    } else if event.kind.is_replaceable() {
+        Some((event.kind, event.pubkey, String::new()))
+    } else {
+        None
+    }
+}
+
+/// Whether `a` supersedes `b`: newer, or as old with a lower id.
+fn newer(a: &Event, b: &Event) -> bool {
+    (a.created_at, std::cmp::Reverse(a.id)) > (b.created_at, std::cmp::Reverse(b.id))
+}
+
+/// Merge the events each relay answered with. Each answer should hold all of
+/// the relay's replaceable and addressable events for the query; a relay
+/// whose answer was cut short is reported as missing the rest.
+pub fn merge(answers: &[(RelayUrl, Vec<Event>)]) -> Merged {
+    let mut first_relay: HashMap<EventId, &RelayUrl> = HashMap::new();
+    let mut others = Vec::new();
+    // Per address: the latest version overall, and each relay's latest.
+    let mut addresses: BTreeMap<Address, (&Event, HashMap<&RelayUrl, &Event>)> = BTreeMap::new();
+
+    for (relay, events) in answers {
+        for event in events {
+            let first = !first_relay.contains_key(&event.id);
+            first_relay.entry(event.id).or_insert(relay);
+            let Some(address) = address(event) else {
+                if first {
+                    others.push(event);
+                }
+                continue;
+            };
+            let (latest, by_relay) = addresses.entry(address).or_insert_with(|| (event, HashMap::new()));
+            if newer(event, latest) {
+                *latest = event;
+            }
+            let best = by_relay.entry(relay).or_insert(event);
+            if newer(event, best) {
+                *best = event;
+            }
+        }
+    }
+
+    let mut events: Vec<(RelayUrl, Event)> =
+        others.into_iter().map(|event| (first_relay[&event.id].clone(), event.clone())).collect();
+    let mut conflicts = Vec::new();
+    let count = addresses.len();
+    for ((kind, pubkey, d), (latest, by_relay)) in addresses {
+        events.push((first_relay[&latest.id].clone(), latest.clone()));
+
+        let mut stale = Vec::new();
+        let mut missing = Vec::new();
+        let mut seen = HashSet::new();
+        for (relay, _) in answers {
+            if !seen.insert(relay) {
+                continue;
+            }
+            match by_relay.get(relay) {
+                Some(best) if best.id == latest.id => {}
+                Some(best) => stale.push((relay.clone(), best.created_at)),
+                None => missing.push(relay.clone()),
+            }
+        }
+        if !stale.is_empty() || !missing.is_empty() {
+            conflicts.push(Conflict {
+                address: format!("{}:{}:{}", kind.as_u16(), pubkey.to_hex(), d),
+                latest: latest.id,
+                latest_at: latest.created_at,
+                stale,
+                missing,
+            });
+        }
+    }
+    Merged { events, addresses: count, conflicts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest_version_per_address() {
+        let keys = Keys::generate();
+        let event = |kind: u16, d: &str, secs: u64, content: &str| {
+            EventBuilder::new(Kind::from(kind), content)
+                .tag(Tag::identifier(d))
+                .custom_created_at(Timestamp::from(1_700_000_000 + secs))
+                .sign_with_keys(&keys)
+                .unwrap()
+        };
+        let relay = |host: &str| RelayUrl::parse(format!("wss://{}", host)).unwrap();
+        let (a, b, c) = (relay("a.example.com"), relay("b.example.com"), relay("c.example.com"));
+
+        let old = event(30472, "phone", 0, "old");
+        let new = event(30472, "phone", 60, "new");
+        // Two versions in the same second: the lower id wins.
+        let (tie1, tie2) = (event(30472, "car", 60, "x"), event(30472, "car", 60, "y"));
+        let (won, lost) = if tie1.id < tie2.id { (tie1, tie2) } else { (tie2, tie1) };
+        let history = event(1472, "phone", 30, "");
+
+        let merged = merge(&[
+            (a.clone(), vec![new.clone(), history.clone(), lost.clone()]),
+            (b.clone(), vec![old.clone(), history.clone(), won.clone()]),
+            (c.clone(), vec![]),
+        ]);
+
+        let mut kept: Vec<(RelayUrl, EventId)> = merged.events.iter().map(|(r, e)| (r.clone(), e.id)).collect();
+        kept.sort_by_key(|(_, id)| *id);
+        let mut expected = vec![(a.clone(), new.id), (a.clone(), history.id), (b.clone(), won.id)];
+        expected.sort_by_key(|(_, id)| *id);
+        assert_eq!(kept, expected);
+
+        let phone = merged.conflicts.iter().find(|c| c.address.ends_with(":phone")).unwrap();
+        assert_eq!(phone.address, format!("30472:{}:phone", keys.public_key().to_hex()));
+        assert_eq!((phone.latest, phone.latest_at), (new.id, new.created_at));
+        assert_eq!(phone.stale, vec![(b.clone(), old.created_at)]);
+        assert_eq!(phone.missing, vec![c.clone()]);
+        assert!(phone.to_string().contains("wss://b.example.com is stale"));
+
+        let car = merged.conflicts.iter().find(|c| c.address.ends_with(":car")).unwrap();
+        assert_eq!(car.latest, won.id);
+        assert_eq!(car.stale, vec![(a, lost.created_at)]);
+
+        // Relays that agree are no conflict.
+        let merged = merge(&[(b.clone(), vec![new.clone()]), (c, vec![old, new])]);
+        assert_eq!(merged.events.len(), 1);
+        assert_eq!(merged.events[0].0, b);
+        assert_eq!(merged.addresses, 1);
+        assert!(merged.conflicts.is_empty());
+    }
+}
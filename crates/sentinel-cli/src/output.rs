@@ -0,0 +1,276 @@
+//! Machine-readable `sentinel query` output. Every fetched location event
+//! becomes one [`Record`], including those that could not be read.
+
+use clap::ValueEnum;
+use nostr_sdk::prelude::*;
+use sentinel_core::event_parser::{self, ParsedLocation};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Aligned columns for reading
+    Table,
+    /// One JSON array
+    Json,
+    /// One JSON object per line
+    Ndjson,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The location was read (and decrypted, if it was encrypted).
+    Ok,
+    /// Encrypted, and no key was given.
+    Encrypted,
+    /// Encrypted, and decrypting or unwrapping with the key failed.
+    DecryptFailed,
+    /// Not a valid location event.
+    Invalid,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Encrypted => "encrypted",
+            Self::DecryptFailed => "decrypt_failed",
+            Self::Invalid => "invalid",
+        }
+    }
+}
+
+/// One location event as seen on one relay. Location fields are empty
+/// unless `status` is `ok`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub event_id: String,
+    pub relay: String,
+    pub kind: u16,
+    /// The tracker: the event author, or the seal signer of a gift wrap.
+    pub pubkey: String,
+    pub d_tag: String,
+    /// Unix seconds; the rumor's for gift wraps.
+    pub timestamp: u64,
+    pub status: Status,
+    /// Whether the location was encrypted (kind 30473/1473 or a gift wrap)
+    /// and we decrypted it.
+    pub decrypted: bool,
+    pub geohash: Option<String>,
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
+    pub accuracy: Option<f64>,
+    pub error: Option<String>,
+}
+
+impl Record {
+    /// A location read from `event`.
+    pub fn location(event: &Event, relay: &RelayUrl, location: ParsedLocation) -> Self {
+        Self {
+            event_id: event.id.to_hex(),
+            relay: relay.to_string(),
+            kind: location.kind,
+            pubkey: location.pubkey,
+            d_tag: location.d_tag,
+            timestamp: location.timestamp.as_u64(),
+            status: Status::Ok,
+            decrypted: event.kind.as_u16() != sentinel_core::kinds::PUBLIC_LOCATION
+                && event.kind.as_u16() != sentinel_core::kinds::PUBLIC_LOCATION_HISTORY,
+            geohash: Some(location.geohash),
+            lat: Some(location.lat),
+            lon: Some(location.lon),
+            accuracy: location.accuracy,
+            error: None,
+        }
+    }
+
+    /// An event whose location could not be read.
+    pub fn unreadable(event: &Event, relay: &RelayUrl, status: Status, error: Option<String>) -> Self {
+        Self {
+            event_id: event.id.to_hex(),
+            relay: relay.to_string(),
+            kind: event.kind.as_u16(),
+            pubkey: event.pubkey.to_hex(),
+            d_tag: event_parser::base_d_tag(event),
+            timestamp: event.created_at.as_u64(),
+            status,
+            decrypted: false,
+            geohash: None,
+            lat: None,
+            lon: None,
+            accuracy: None,
+            error,
+        }
+    }
+
+    fn fields(&self) -> [String; 13] {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        [
+            self.event_id.clone(),
+            self.relay.clone(),
+            self.kind.to_string(),
+            self.pubkey.clone(),
+            self.d_tag.clone(),
+            self.timestamp.to_string(),
+            self.status.as_str().to_string(),
+            self.decrypted.to_string(),
+            opt(self.geohash.clone()),
+            opt(self.lat.map(|v| format!("{:.6}", v))),
+            opt(self.lon.map(|v| format!("{:.6}", v))),
+            opt(self.accuracy.map(|v| v.to_string())),
+            opt(self.error.clone()),
+        ]
+    }
+}
+
+const CSV_HEADER: [&str; 13] = [
+    "event_id", "relay", "kind", "pubkey", "d_tag", "timestamp", "status", "decrypted", "geohash",
+    "lat", "lon", "accuracy", "error",
+];
+
+/// Write `records` to `out` in `format`.
+pub fn write(out: &mut impl Write, format: Format, records: &[Record]) -> std::io::Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut *out, records)?;
+            writeln!(out)
+        }
+        Format::Ndjson => {
+            for record in records {
+                serde_json::to_writer(&mut *out, record)?;
+                writeln!(out)?;
+            }
+            Ok(())
+        }
+        Format::Csv => {
+            writeln!(out, "{}", CSV_HEADER.join(","))?;
+            for record in records {
+                let row: Vec<String> = record.fields().iter().map(|f| csv_field(f)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+            Ok(())
+        }
+        Format::Table => write_table(out, records),
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn write_table(out: &mut impl Write, records: &[Record]) -> std::io::Result<()> {
+    let header = ["TIME", "KIND", "D_TAG", "LAT", "LON", "ACC", "STATUS", "RELAY", "EVENT"];
+    let rows: Vec<[String; 9]> = records
+        .iter()
+        .map(|r| {
+            let status = match (&r.error, r.decrypted) {
+                (Some(e), _) => format!("{}: {}", r.status.as_str(), e),
+                (None, true) => "decrypted".to_string(),
+                (None, false) => r.status.as_str().to_string(),
+            };
+            let fields = r.fields();
+            [
+                Timestamp::from(r.timestamp).to_human_datetime(),
+                fields[2].clone(),
+                r.d_tag.clone(),
+                fields[9].clone(),
+                fields[10].clone(),
+                r.accuracy.map(|v| format!("{}m", v)).unwrap_or_default(),
+                status,
+                r.relay.clone(),
+                r.event_id.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> =
+            cells.iter().zip(widths).map(|(cell, width)| format!("{:<width$}", cell)).collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    writeln!(out, "{}", line(header.to_vec()))?;
+    for row in &rows {
+        writeln!(out, "{}", line(row.iter().map(String::as_str).collect()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentinel_core::config::TrackingConfig;
+    use sentinel_core::event_builder;
+
+    fn records() -> Vec<Record> {
+        let keys = Keys::generate();
+        let relay = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let config = TrackingConfig { d_tag: "phone".into(), ..TrackingConfig::default() };
+        let public = event_builder::build_location_events(60.1699, 24.9384, Some(10.0), &keys, &config)
+            .unwrap()
+            .remove(0);
+        let location = event_parser::parse_public_event(&public).unwrap();
+
+        let recipient = Keys::generate();
+        let config = TrackingConfig {
+            d_tag: "car, \"blue\"".into(),
+            encrypted: true,
+            recipient_pubkeys: vec![recipient.public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+        let encrypted = event_builder::build_location_events(1.0, 2.0, None, &keys, &config).unwrap().remove(0);
+        vec![
+            Record::location(&public, &relay, location),
+            Record::unreadable(&encrypted, &relay, Status::Encrypted, None),
+        ]
+    }
+
+    fn render(format: Format, records: &[Record]) -> String {
+        let mut out = Vec::new();
+        write(&mut out, format, records).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats_share_one_schema() {
+        let records = records();
+
+        let json: serde_json::Value = serde_json::from_str(&render(Format::Json, &records)).unwrap();
+        assert_eq!(json[0]["status"], "ok");
+        assert_eq!(json[0]["relay"], "wss://relay.example.com");
+        assert_eq!(json[0]["decrypted"], false);
+        assert_eq!(json[0]["accuracy"], 10.0);
+        assert_eq!(json[1]["status"], "encrypted");
+        assert_eq!(json[1]["kind"], 30473);
+        assert_eq!(json[1]["lat"], serde_json::Value::Null);
+
+        let ndjson = render(Format::Ndjson, &records);
+        let lines: Vec<serde_json::Value> =
+            ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines, json.as_array().unwrap().clone());
+
+        let csv = render(Format::Csv, &records);
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows[0], CSV_HEADER.join(","));
+        assert!(rows[1].contains(",phone,") && rows[1].contains(",ok,false,") && rows[1].contains(",60.169"));
+        assert!(rows[2].contains(r#","car, ""blue""","#), "{}", rows[2]);
+
+        let table = render(Format::Table, &records);
+        assert!(table.starts_with("TIME"));
+        assert_eq!(table.lines().count(), 3);
+        assert!(table.lines().nth(1).unwrap().contains(" 10m "));
+        assert!(table.lines().nth(2).unwrap().contains("encrypted"));
+    }
+}
@@ -0,0 +1,243 @@
+//! `sentinel publish` and `sentinel track`: build location events with
+//! `sentinel_core::event_builder` from a [`TrackingConfig`] and send them.
+
+use clap::Args;
+use nostr_sdk::prelude::*;
+use sentinel_core::config::TrackingConfig;
+use sentinel_core::event_builder;
+use sentinel_core::policy::{PublishPolicy, PublishTracker};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::relay_auth::{self, RelayAuth};
+use crate::source::{Fix, LocationSource};
+use crate::{parse_duration_str, parse_pubkey, signing_keys};
+
+#[derive(Debug, Args)]
+pub struct PublishArgs {
+    /// Device identifier (`d` tag)
+    #[arg(long, default_value = "default")]
+    d_tag: String,
+
+    /// Encrypt to this recipient (hex or npub, repeatable); publishes kind 30473
+    #[arg(long)]
+    encrypt_to: Vec<String>,
+
+    /// Send NIP-59 gift wraps (kind 1059) to the recipients instead
+    #[arg(long, requires = "encrypt_to")]
+    gift_wrap: bool,
+
+    /// Publish regular kind 1472/1473 events that relays keep as a track
+    #[arg(long)]
+    history: bool,
+
+    /// Geohash precision (1-12)
+    #[arg(long, default_value_t = 8)]
+    precision: u8,
+
+    /// Let relays drop events after this long ("0" keeps them; default 1h,
+    /// history and imports are kept)
+    #[arg(long)]
+    expiration: Option<String>,
+
+    /// Relay URLs
+    #[arg(long, default_value = "wss://zooid.atlantislabs.space")]
+    relays: Vec<String>,
+
+    /// Stored identity to sign with instead of the default
+    #[arg(long)]
+    identity: Option<String>,
+}
+
+impl PublishArgs {
+    /// Tracking config for these options, publishing every `interval`.
+    pub fn config(&self, interval: Duration) -> Result<TrackingConfig> {
+        let recipient_pubkeys = self
+            .encrypt_to
+            .iter()
+            .map(|pk| Ok(parse_pubkey(pk)?.to_hex()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(TrackingConfig {
+            interval_secs: interval.as_secs(),
+            precision: self.precision,
+            encrypted: !recipient_pubkeys.is_empty() && !self.gift_wrap,
+            gift_wrap: self.gift_wrap,
+            recipient_pubkeys,
+            relays: self.relays.clone(),
+            d_tag: self.d_tag.clone(),
+            expiration_secs: self
+                .expiration
+                .as_deref()
+                .map(|expiration| parse_duration_str(expiration).map(|ttl| ttl.as_secs()))
+                .transpose()?,
+            history: self.history,
+        })
+    }
+
+    /// Tracking config for imported history: history kinds, which are kept
+    /// unless `--expiration` is given.
+    pub fn import_config(&self) -> Result<TrackingConfig> {
+        let mut config = self.config(Duration::ZERO)?;
+        config.history = true;
+        Ok(config)
+    }
+
+    /// The key to sign with: `--identity` or the default identity.
+    pub fn keys(&self) -> Result<Keys> {
+        signing_keys(self.identity.as_deref())
+    }
+}
+
+/// When `track` publishes a reading (see [`PublishPolicy`]).
+#[derive(Debug, Args)]
+pub struct PolicyArgs {
+    /// Never publish more often than this
+    #[arg(long, default_value = "0")]
+    min_interval: String,
+
+    /// Publish at least this often while standing still ("0": only on movement)
+    #[arg(long, default_value = "15m")]
+    heartbeat: String,
+
+    /// Meters beyond the fix accuracy that count as movement
+    #[arg(long, default_value_t = 0.0)]
+    min_distance: f64,
+}
+
+impl PolicyArgs {
+    /// The policy at the geohash precision of `config`.
+    pub fn policy(&self, config: &TrackingConfig) -> Result<PublishPolicy> {
+        Ok(PublishPolicy {
+            min_interval_secs: parse_duration_str(&self.min_interval)?.as_secs(),
+            max_interval_secs: parse_duration_str(&self.heartbeat)?.as_secs(),
+            min_distance_m: self.min_distance,
+            precision: config.precision,
+        })
+    }
+}
+
+/// Signs and sends locations for one [`TrackingConfig`].
+pub struct Publisher {
+    client: Client,
+    auth: Arc<RelayAuth>,
+    keys: Keys,
+    config: TrackingConfig,
+}
+
+impl Publisher {
+    pub async fn connect(args: &PublishArgs, interval: Duration) -> Result<Self> {
+        Self::connect_with(args.keys()?, args.config(interval)?).await
+    }
+
+    pub async fn connect_with(keys: Keys, config: TrackingConfig) -> Result<Self> {
+        let (client, auth) = relay_auth::client(Some(&keys));
+        for relay in &config.relays {
+            client.add_relay(relay).await?;
+        }
+        client.connect_with_timeout(Duration::from_secs(10)).await;
+        Ok(Self { client, auth, keys, config })
+    }
+
+    pub fn config(&self) -> &TrackingConfig {
+        &self.config
+    }
+
+    /// Publish `fix`, reporting each event per relay. Fails if no relay
+    /// accepted any of the events.
+    pub async fn publish(&self, fix: &Fix) -> Result<()> {
+        self.publish_at(fix, None).await
+    }
+
+    /// [`Publisher::publish`] with the events dated `created_at` instead of now.
+    pub async fn publish_at(&self, fix: &Fix, created_at: Option<Timestamp>) -> Result<()> {
+        let events = event_builder::build_location_events_at(
+            fix.lat,
+            fix.lon,
+            fix.accuracy,
+            created_at,
+            &self.keys,
+            &self.config,
+        )?;
+        let mut accepted = false;
+        for event in &events {
+            let results = relay_auth::send_event(&self.client, &self.auth, event).await;
+            let ok = results.iter().filter(|(_, r)| r.is_ok()).count();
+            for (relay, result) in &results {
+                if let Err(e) = result {
+                    eprintln!("  {} rejected {}: {}", relay, event.id, e);
+                }
+            }
+            println!(
+                "Published kind {} {} ({:.6},{:.6}) to {}/{} relays",
+                event.kind.as_u16(),
+                event.id,
+                fix.lat,
+                fix.lon,
+                ok,
+                results.len()
+            );
+            accepted |= ok > 0;
+        }
+        if accepted {
+            Ok(())
+        } else {
+            Err("No relay accepted the location".into())
+        }
+    }
+}
+
+/// Publish one location.
+pub async fn cmd_publish(fix: Fix, args: &PublishArgs) -> Result<()> {
+    let publisher = Publisher::connect(args, Duration::ZERO).await?;
+    publisher.publish(&fix).await
+}
+
+/// Read `source` every `interval` and publish the readings the policy
+/// picks, until the source is finished. Readings without a fix and failed
+/// publishes are reported and skipped.
+pub async fn cmd_track(
+    mut source: Box<dyn LocationSource>,
+    interval: Duration,
+    args: &PublishArgs,
+    policy: &PolicyArgs,
+) -> Result<()> {
+    let publisher = Publisher::connect(args, interval).await?;
+    let mut tracker = PublishTracker::new(policy.policy(publisher.config())?);
+    eprintln!(
+        "Tracking {} as {} every {}s",
+        source.name(),
+        publisher.config().d_tag,
+        publisher.config().interval_secs
+    );
+
+    let mut ticks = tokio::time::interval(Duration::from_secs(publisher.config().interval_secs.max(1)));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    while !source.finished() {
+        ticks.tick().await;
+        let fix = match source.current().await {
+            Ok(Some(fix)) => fix,
+            Ok(None) => {
+                eprintln!("No fix from {}; skipping", source.name());
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Reading {} failed: {}", source.name(), e);
+                continue;
+            }
+        };
+        let now = fix.time.unwrap_or_else(Timestamp::now);
+        match tracker.check(fix.lat, fix.lon, fix.accuracy, now) {
+            Ok(Some(reason)) => {
+                eprintln!("Publishing ({})", reason.as_str().replace('_', " "));
+                match publisher.publish(&fix).await {
+                    Ok(()) => tracker.record(fix.lat, fix.lon, fix.accuracy, now)?,
+                    Err(e) => eprintln!("Publish failed: {}", e),
+                }
+            }
+            Ok(None) => eprintln!("No significant movement; skipping"),
+            Err(e) => eprintln!("Skipping invalid fix: {}", e),
+        }
+    }
+    eprintln!("{} has no more fixes; stopping", source.name());
+    Ok(())
+}
@@ -0,0 +1,572 @@
+//! NIP-42 relay authentication. The client signs AUTH challenges with the
+//! CLI identity and reports the outcome per relay; fetches refused with
+//! `auth-required` are retried once the relay has accepted the AUTH.
+
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Notify;
+
+/// How long a refused fetch waits for the AUTH round trip before giving up.
+const AUTH_WAIT: Duration = Duration::from_secs(5);
+/// Most pages fetched for one filter from one relay.
+const MAX_PAGES: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthState {
+    Authenticated,
+    Failed(String),
+}
+
+/// Why a relay gave no answer to a fetch.
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    /// The relay refused the request (`auth-required:` or `restricted:`),
+    /// also after our AUTH if we could send one.
+    #[error("refused: {0}")]
+    Refused(String),
+}
+
+#[derive(Default)]
+struct Relays {
+    states: HashMap<RelayUrl, AuthState>,
+    /// Subscriptions closed with `auth-required:` or `restricted:` so far,
+    /// and the relay's last such message.
+    refusals: HashMap<RelayUrl, (u64, String)>,
+}
+
+/// Per-relay AUTH outcomes, fed by the notification handler of [`client`].
+pub struct RelayAuth {
+    signer: bool,
+    relays: Mutex<Relays>,
+    changed: Notify,
+}
+
+impl RelayAuth {
+    fn new(signer: bool) -> Self {
+        Self { signer, relays: Mutex::new(Relays::default()), changed: Notify::new() }
+    }
+
+    pub fn state(&self, relay: &RelayUrl) -> Option<AuthState> {
+        self.relays.lock().unwrap().states.get(relay).cloned()
+    }
+
+    fn set_state(&self, relay: RelayUrl, state: AuthState) {
+        self.relays.lock().unwrap().states.insert(relay, state);
+        self.changed.notify_waiters();
+    }
+
+    fn refused(&self, relay: RelayUrl, message: String) {
+        let mut relays = self.relays.lock().unwrap();
+        let refusals = relays.refusals.entry(relay).or_default();
+        *refusals = (refusals.0 + 1, message);
+        drop(relays);
+        self.changed.notify_waiters();
+    }
+
+    fn refusals(&self, relay: &RelayUrl) -> u64 {
+        self.relays.lock().unwrap().refusals.get(relay).map_or(0, |(count, _)| *count)
+    }
+
+    /// The relay's last refusal, with the AUTH outcome if AUTH failed.
+    fn refusal(&self, relay: &RelayUrl) -> FetchError {
+        let message = self.relays.lock().unwrap().refusals.get(relay).map(|(_, message)| message.clone());
+        let message = message.unwrap_or_default();
+        FetchError::Refused(match self.state(relay) {
+            Some(AuthState::Failed(reason)) => format!("{} (AUTH: {})", message, reason),
+            _ => message,
+        })
+    }
+
+    /// Wait until `relay` refuses a subscription after `seen` earlier refusals.
+    async fn refused_after(&self, relay: &RelayUrl, seen: u64) {
+        loop {
+            let changed = self.changed.notified();
+            if self.refusals(relay) > seen {
+                return;
+            }
+            changed.await;
+        }
+    }
+
+    /// Wait for `relay` to accept our AUTH; false if it failed, we have no
+    /// identity, or it took longer than [`AUTH_WAIT`].
+    async fn wait_authenticated(&self, relay: &RelayUrl) -> bool {
+        if !self.signer {
+            return false;
+        }
+        tokio::time::timeout(AUTH_WAIT, async {
+            loop {
+                let changed = self.changed.notified();
+                match self.state(relay) {
+                    Some(AuthState::Authenticated) => return true,
+                    Some(AuthState::Failed(_)) => return false,
+                    None => changed.await,
+                }
+            }
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+/// A client signing with `keys` that answers AUTH challenges and reports the
+/// outcome per relay on stderr. Without keys, challenges are reported as
+/// unanswerable.
+pub fn client(keys: Option<&Keys>) -> (Client, Arc<RelayAuth>) {
+    let opts = Options::new().automatic_authentication(false);
+    let client = match keys {
+        Some(keys) => Client::builder().signer(keys.clone()).opts(opts).build(),
+        None => Client::builder().opts(opts).build(),
+    };
+    let auth = Arc::new(RelayAuth::new(keys.is_some()));
+
+    // Subscribe before any relay connects so no challenge is missed.
+    let mut notifications = client.notifications();
+    let handler_client = client.clone();
+    let handler_auth = Arc::clone(&auth);
+    tokio::spawn(async move {
+        loop {
+            let notification = match notifications.recv().await {
+                Ok(notification) => notification,
+                // Missed messages; keep answering the challenges that follow.
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            match notification {
+                RelayPoolNotification::Message { relay_url, message } => {
+                    if matches!(message, RelayMessage::Auth { .. }) {
+                        // The AUTH round trip runs on its own so this loop
+                        // keeps up with the pool's notifications.
+                        let client = handler_client.clone();
+                        let auth = Arc::clone(&handler_auth);
+                        tokio::spawn(async move { handle_message(&client, &auth, relay_url, message).await });
+                    } else {
+                        handle_message(&handler_client, &handler_auth, relay_url, message).await;
+                    }
+                }
+                RelayPoolNotification::Shutdown => break,
+                _ => {}
+            }
+        }
+    });
+    (client, auth)
+}
+
+async fn handle_message(client: &Client, auth: &RelayAuth, relay: RelayUrl, message: RelayMessage) {
+    match message {
+        RelayMessage::Auth { challenge } if auth.signer => {
+            match client.auth(challenge, relay.clone()).await {
+                Ok(()) => {
+                    eprintln!("Authenticated to {}", relay);
+                    auth.set_state(relay.clone(), AuthState::Authenticated);
+                    // Subscriptions the relay closed before AUTH.
+                    if let Ok(relay) = client.relay(&relay).await {
+                        if let Err(e) = relay.resubscribe().await {
+                            eprintln!("Could not resubscribe to {}: {}", relay.url(), e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("AUTH to {} failed: {}", relay, e);
+                    auth.set_state(relay, AuthState::Failed(e.to_string()));
+                }
+            }
+        }
+        RelayMessage::Auth { .. } if auth.state(&relay).is_none() => {
+            eprintln!(
+                "{} asks for AUTH but no identity is configured (see `sentinel identity`)",
+                relay
+            );
+            auth.set_state(relay, AuthState::Failed("no identity configured".into()));
+        }
+        RelayMessage::Closed { message, .. }
+            if message.starts_with("auth-required:") || message.starts_with("restricted:") =>
+        {
+            eprintln!("{} refused subscription: {}", relay, message);
+            auth.refused(relay, message);
+        }
+        _ => {}
+    }
+}
+
+/// Fetch `filters` from every relay of `client`. A relay that refuses the
+/// request for lack of AUTH is asked again once our AUTH is accepted instead
+/// of holding the fetch until `timeout`; if it still refuses, its answer is a
+/// [`FetchError::Refused`]. Fails only if every relay failed.
+pub async fn fetch_events(
+    client: &Client,
+    auth: &Arc<RelayAuth>,
+    filters: Vec<Filter>,
+    timeout: Duration,
+) -> Result<Events, FetchError> {
+    let mut events = Events::new(&filters);
+    let mut error = None;
+    let mut fetched = false;
+    for (_, result) in fetch_events_by_relay(client, auth, filters, timeout).await {
+        match result {
+            Ok(relay_events) => {
+                events = events.merge(relay_events);
+                fetched = true;
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    match error {
+        Some(e) if !fetched => Err(e),
+        _ => Ok(events),
+    }
+}
+
+/// Like [`fetch_events`], but keeps each relay's answer apart, sorted by URL.
+pub async fn fetch_events_by_relay(
+    client: &Client,
+    auth: &Arc<RelayAuth>,
+    filters: Vec<Filter>,
+    timeout: Duration,
+) -> Vec<(RelayUrl, Result<Events, FetchError>)> {
+    let mut fetches = tokio::task::JoinSet::new();
+    for url in client.relays().await.into_keys() {
+        let client = client.clone();
+        let auth = Arc::clone(auth);
+        let filters = filters.clone();
+        fetches.spawn(async move {
+            let result = fetch_from(&client, &auth, url.clone(), filters, timeout).await;
+            (url, result)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = fetches.join_next().await {
+        results.push(result.expect("fetch task panicked"));
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+/// Like [`fetch_events`], but pages back through each filter with a `limit`
+/// as [`fetch_pages_by_relay`] does. Each event comes with the first relay
+/// by URL that returned it. Events are newest first.
+pub async fn fetch_pages(
+    client: &Client,
+    auth: &Arc<RelayAuth>,
+    filters: Vec<Filter>,
+    timeout: Duration,
+    more: fn(&Event) -> bool,
+) -> Result<Vec<(RelayUrl, Event)>, FetchError> {
+    let mut events: HashMap<EventId, (RelayUrl, Event)> = HashMap::new();
+    let mut error = None;
+    let mut fetched = false;
+    for (relay, result) in fetch_pages_by_relay(client, auth, filters, timeout, more).await {
+        match result {
+            Ok(relay_events) => {
+                for event in relay_events {
+                    events.entry(event.id).or_insert_with(|| (relay.clone(), event));
+                }
+                fetched = true;
+            }
+            Err(e) => error = Some(e),
+        }
+    }
+    if let (Some(e), false) = (error, fetched) {
+        return Err(e);
+    }
+    let mut events: Vec<(RelayUrl, Event)> = events.into_values().collect();
+    events.sort_by(|(_, a), (_, b)| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+    Ok(events)
+}
+
+/// Like [`fetch_events_by_relay`], but pages back through each filter with a
+/// `limit`: while a relay answers with a full page holding an event for which
+/// `more` is true, the filter is sent again with `until` at the oldest event
+/// of the page, for at most [`MAX_PAGES`] pages. Events are newest first.
+pub async fn fetch_pages_by_relay(
+    client: &Client,
+    auth: &Arc<RelayAuth>,
+    filters: Vec<Filter>,
+    timeout: Duration,
+    more: fn(&Event) -> bool,
+) -> Vec<(RelayUrl, Result<Vec<Event>, FetchError>)> {
+    let mut fetches = tokio::task::JoinSet::new();
+    for url in client.relays().await.into_keys() {
+        let client = client.clone();
+        let auth = Arc::clone(auth);
+        let filters = filters.clone();
+        fetches.spawn(async move {
+            let mut events: HashMap<EventId, Event> = HashMap::new();
+            for filter in filters {
+                match fetch_pages_from(&client, &auth, &url, filter, timeout, more).await {
+                    Ok(page) => events.extend(page),
+                    Err(e) => return (url, Err(e)),
+                }
+            }
+            let mut events: Vec<Event> = events.into_values().collect();
+            events.sort_by(|a, b| b.created_at.cmp(&a.created_at).then_with(|| a.id.cmp(&b.id)));
+            (url, Ok(events))
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = fetches.join_next().await {
+        results.push(result.expect("fetch task panicked"));
+    }
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+/// All events of `filter` on `url`, page by page as for [`fetch_pages_by_relay`].
+async fn fetch_pages_from(
+    client: &Client,
+    auth: &RelayAuth,
+    url: &RelayUrl,
+    mut filter: Filter,
+    timeout: Duration,
+    more: fn(&Event) -> bool,
+) -> Result<HashMap<EventId, Event>, FetchError> {
+    let mut events = HashMap::new();
+    // Whether the last page brought nothing new and `until` was moved past it.
+    let mut stepped_back = false;
+    for _ in 0..MAX_PAGES {
+        let page = fetch_from(client, auth, url.clone(), vec![filter.clone()], timeout).await?;
+        let full = filter.limit.is_some_and(|limit| page.len() >= limit);
+        let follow = full && page.iter().any(more);
+        let oldest = page.iter().map(|event| event.created_at).min();
+        let before = events.len();
+        events.extend(page.into_iter().map(|event| (event.id, event)));
+        // `until` is inclusive, so the next page starts in the second of the
+        // oldest event, unless the whole page was in that second already
+        // (more events in one second than fit a page are lost). A relay that
+        // still answers with nothing new after that ignores `until`.
+        match oldest {
+            Some(oldest) if follow && events.len() > before => {
+                filter = filter.until(oldest);
+                stepped_back = false;
+            }
+            Some(oldest) if follow && !stepped_back && oldest.as_u64() > 0 => {
+                filter = filter.until(oldest - 1);
+                stepped_back = true;
+            }
+            _ => return Ok(events),
+        }
+    }
+    eprintln!("Stopped paging {} after {} pages", url, MAX_PAGES);
+    Ok(events)
+}
+
+async fn fetch_from(
+    client: &Client,
+    auth: &RelayAuth,
+    url: RelayUrl,
+    filters: Vec<Filter>,
+    timeout: Duration,
+) -> Result<Events, FetchError> {
+    for retry in [false, true] {
+        let seen = auth.refusals(&url);
+        tokio::select! {
+            result = client.fetch_events_from([url.clone()], filters.clone(), Some(timeout)) => {
+                let events = result?;
+                // A refusal may end the fetch before it is seen here.
+                if auth.refusals(&url) == seen {
+                    return Ok(events);
+                }
+            }
+            _ = auth.refused_after(&url, seen) => {}
+        }
+        if retry || !auth.wait_authenticated(&url).await {
+            break;
+        }
+    }
+    Err(auth.refusal(&url))
+}
+
+/// Send `event` to every connected relay of `client`, retrying relays that
+/// answer `auth-required:` once our AUTH is accepted. Returns the outcome per relay,
+/// sorted by URL.
+pub async fn send_event(
+    client: &Client,
+    auth: &Arc<RelayAuth>,
+    event: &Event,
+) -> Vec<(RelayUrl, Result<(), String>)> {
+    let mut sends = tokio::task::JoinSet::new();
+    for (url, relay) in client.relays().await {
+        let auth = Arc::clone(auth);
+        let event = event.clone();
+        sends.spawn(async move {
+            if !relay.is_connected() {
+                return (url, Err("not connected".to_string()));
+            }
+            let mut result = relay.send_event(event.clone()).await.map(|_| ()).map_err(|e| e.to_string());
+            if matches!(&result, Err(e) if e.contains("auth-required:")) && auth.wait_authenticated(&url).await {
+                result = relay.send_event(event).await.map(|_| ()).map_err(|e| e.to_string());
+            }
+            (url, result)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(result) = sends.join_next().await {
+        results.push(result.expect("send task panicked"));
+    }
+    results.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message;
+
+    const CHALLENGE: &str = "sentinel-test-challenge";
+
+    /// Relay that serves `events` only to connections authenticated as `allowed`.
+    async fn auth_relay(allowed: PublicKey, events: Vec<Event>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let events = events.clone();
+                tokio::spawn(async move {
+                    let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                    let send = |msg: RelayMessage| Message::text(msg.as_json());
+                    ws.send(send(RelayMessage::auth(CHALLENGE))).await.unwrap();
+                    let mut authed = false;
+                    while let Some(Ok(msg)) = ws.next().await {
+                        let Message::Text(text) = msg else { continue };
+                        let reply = match ClientMessage::from_json(text.as_str()).unwrap() {
+                            ClientMessage::Auth(event) => {
+                                let challenge_ok = event.tags.iter().any(|t| {
+                                    t.kind() == TagKind::Challenge && t.content() == Some(CHALLENGE)
+                                });
+                                authed = event.kind == Kind::Authentication
+                                    && event.verify().is_ok()
+                                    && challenge_ok
+                                    && event.pubkey == allowed;
+                                let reason = if authed { "" } else { "restricted: not a member" };
+                                vec![RelayMessage::ok(event.id, authed, reason)]
+                            }
+                            ClientMessage::Event(event) if !authed => {
+                                vec![RelayMessage::ok(event.id, false, "auth-required: members only")]
+                            }
+                            ClientMessage::Event(event) => vec![RelayMessage::ok(event.id, true, "")],
+                            ClientMessage::Req { subscription_id, .. } if !authed => {
+                                vec![RelayMessage::closed(subscription_id, "auth-required: members only")]
+                            }
+                            ClientMessage::Req { subscription_id, filters } => {
+                                let mut reply = Vec::new();
+                                for filter in filters {
+                                    let mut matching: Vec<&Event> =
+                                        events.iter().filter(|e| filter.match_event(e)).collect();
+                                    matching.sort_by_key(|e| std::cmp::Reverse(e.created_at));
+                                    matching.truncate(filter.limit.unwrap_or(usize::MAX));
+                                    reply.extend(
+                                        matching
+                                            .into_iter()
+                                            .map(|e| RelayMessage::event(subscription_id.clone(), e.clone())),
+                                    );
+                                }
+                                reply.push(RelayMessage::eose(subscription_id));
+                                reply
+                            }
+                            _ => Vec::new(),
+                        };
+                        for msg in reply {
+                            ws.send(send(msg)).await.unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        url
+    }
+
+    async fn fetch(url: &str, keys: Option<&Keys>) -> (Result<Events, FetchError>, Option<AuthState>) {
+        let (client, auth) = client(keys);
+        client.add_relay(url).await.unwrap();
+        client.connect().await;
+        let filters = vec![Filter::new().kind(Kind::TextNote)];
+        let started = std::time::Instant::now();
+        let events = fetch_events(&client, &auth, filters, Duration::from_secs(20)).await;
+        // Refusals end the fetch instead of running into the timeout.
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let state = auth.state(&RelayUrl::parse(url).unwrap());
+        (events, state)
+    }
+
+    #[tokio::test]
+    async fn fetch_authenticates_with_identity() {
+        let member = Keys::generate();
+        let note = EventBuilder::text_note("members only").sign_with_keys(&member).unwrap();
+        let url = auth_relay(member.public_key(), vec![note.clone()]).await;
+
+        let (events, state) = fetch(&url, Some(&member)).await;
+        assert_eq!(state, Some(AuthState::Authenticated));
+        assert_eq!(events.unwrap().to_vec(), vec![note]);
+
+        // A refusal is an error, not an empty answer.
+        let (events, state) = fetch(&url, None).await;
+        assert!(matches!(events, Err(FetchError::Refused(message))
+            if message.starts_with("auth-required:") && message.contains("no identity configured")));
+        assert_eq!(state, Some(AuthState::Failed("no identity configured".into())));
+
+        let (events, state) = fetch(&url, Some(&Keys::generate())).await;
+        assert!(matches!(events, Err(FetchError::Refused(message)) if message.contains("not a member")));
+        assert!(matches!(state, Some(AuthState::Failed(reason)) if reason.contains("not a member")));
+    }
+
+    #[tokio::test]
+    async fn fetch_pages_follows_full_pages() {
+        let member = Keys::generate();
+        let note = |kind: u16, secs: u64, content: &str| {
+            EventBuilder::new(Kind::from(kind), content)
+                .custom_created_at(Timestamp::from(1_700_000_000 + secs))
+                .sign_with_keys(&member)
+                .unwrap()
+        };
+        // Two notes share a second, so pages overlap at their edges.
+        let history: Vec<Event> =
+            [0, 10, 20, 20, 30, 40].into_iter().enumerate().map(|(i, secs)| note(1472, secs, &i.to_string())).collect();
+        let texts: Vec<Event> = (0..3).map(|secs| note(1, secs, "")).collect();
+        let url = auth_relay(member.public_key(), [history.clone(), texts].concat()).await;
+
+        let (client, auth) = client(Some(&member));
+        client.add_relay(&url).await.unwrap();
+        client.connect().await;
+        let fetch = |kind: u16| {
+            let filters = vec![Filter::new().kind(Kind::from(kind)).limit(2)];
+            fetch_pages_by_relay(&client, &auth, filters, Duration::from_secs(10), |e| e.kind.as_u16() == 1472)
+        };
+
+        let results = fetch(1472).await;
+        let events = results[0].1.as_ref().unwrap();
+        assert_eq!(events.len(), history.len());
+        assert!(events.windows(2).all(|pair| pair[0].created_at >= pair[1].created_at));
+
+        // Pages without history events are not followed.
+        assert_eq!(fetch(1).await[0].1.as_ref().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn publish_waits_for_auth() {
+        let member = Keys::generate();
+        let url = auth_relay(member.public_key(), Vec::new()).await;
+        let note = EventBuilder::text_note("hello").sign_with_keys(&member).unwrap();
+
+        let (client, auth) = super::client(Some(&member));
+        client.add_relay(&url).await.unwrap();
+        client.connect_with_timeout(Duration::from_secs(5)).await;
+        let results = send_event(&client, &auth, &note).await;
+        assert_eq!(results, vec![(RelayUrl::parse(&url).unwrap(), Ok(()))]);
+
+        let (client, auth) = super::client(None);
+        client.add_relay(&url).await.unwrap();
+        client.connect_with_timeout(Duration::from_secs(5)).await;
+        let results = send_event(&client, &auth, &note).await;
+        assert!(matches!(&results[0].1, Err(e) if e.contains("auth-required")));
+    }
+}
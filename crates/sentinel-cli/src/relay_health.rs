@@ -0,0 +1,130 @@
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Connection state of one relay.
+#[derive(Debug, Clone)]
+struct RelayState {
+    connected: bool,
+    /// When `connected` last changed.
+    since: Timestamp,
+    alerted: bool,
+}
+
+/// A relay crossing the unreachable threshold, or coming back after it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelayEvent {
+    Down { relay: String, since: Timestamp },
+    Up { relay: String, down_secs: u64 },
+}
+
+/// Tracks relay connectivity so an outage on our side can be told apart from
+/// a silent device.
+#[derive(Debug, Clone)]
+pub struct RelayHealth {
+    grace: u64,
+    relays: HashMap<String, RelayState>,
+    /// Last moment at least one relay was connected.
+    last_connected: Option<Timestamp>,
+}
+
+impl RelayHealth {
+    /// A relay is reported unreachable once it has been disconnected for `grace`.
+    pub fn new(grace: Duration) -> Self {
+        Self { grace: grace.as_secs(), relays: HashMap::new(), last_connected: None }
+    }
+
+    /// Record a status change. Returns `Up` if the relay had been reported down.
+    pub fn update(&mut self, relay: &str, status: RelayStatus, now: Timestamp) -> Option<RelayEvent> {
+        let connected = status == RelayStatus::Connected;
+        let Some(state) = self.relays.get_mut(relay) else {
+            let state = RelayState { connected, since: now, alerted: false };
+            self.relays.insert(relay.to_string(), state);
+            return None;
+        };
+        if state.connected == connected {
+            return None;
+        }
+        if state.connected {
+            self.last_connected = Some(now);
+        }
+        let down_secs = now.as_u64().saturating_sub(state.since.as_u64());
+        state.connected = connected;
+        state.since = now;
+
+        if connected && state.alerted {
+            state.alerted = false;
+            return Some(RelayEvent::Up { relay: relay.to_string(), down_secs });
+        }
+        None
+    }
+
+    /// Relays that have been disconnected for longer than the grace period,
+    /// each reported once per outage.
+    pub fn check(&mut self, now: Timestamp) -> Vec<RelayEvent> {
+        let mut down: Vec<(String, Timestamp)> = self
+            .relays
+            .iter_mut()
+            .filter(|(_, s)| !s.connected && !s.alerted && now >= s.since + self.grace)
+            .map(|(relay, s)| {
+                s.alerted = true;
+                (relay.clone(), s.since)
+            })
+            .collect();
+        down.sort();
+        down.into_iter().map(|(relay, since)| RelayEvent::Down { relay, since }).collect()
+    }
+
+    /// Whether any relay was connected at some point in `[start, now]`.
+    pub fn any_connected_since(&self, start: Timestamp) -> bool {
+        self.relays.values().any(|s| s.connected) || self.last_connected.is_some_and(|t| t >= start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relay_down_after_grace_and_up_once() {
+        let t0 = Timestamp::from(1_000_000);
+        let mut health = RelayHealth::new(Duration::from_secs(60));
+        let relay = "wss://relay.example.com";
+
+        assert_eq!(health.update(relay, RelayStatus::Connecting, t0), None);
+        assert!(!health.any_connected_since(t0));
+        assert_eq!(health.update(relay, RelayStatus::Connected, t0 + 5u64), None);
+        assert!(health.any_connected_since(t0));
+
+        let t1 = t0 + 100u64;
+        health.update(relay, RelayStatus::Disconnected, t1);
+        // Reconnecting is still disconnected.
+        health.update(relay, RelayStatus::Connecting, t1 + 10u64);
+        assert!(health.check(t1 + 59u64).is_empty());
+        assert_eq!(
+            health.check(t1 + 60u64),
+            vec![RelayEvent::Down { relay: relay.to_string(), since: t1 }]
+        );
+        assert!(health.check(t1 + 600u64).is_empty());
+
+        assert!(health.any_connected_since(t1));
+        assert!(!health.any_connected_since(t1 + 1u64));
+
+        assert_eq!(
+            health.update(relay, RelayStatus::Connected, t1 + 300u64),
+            Some(RelayEvent::Up { relay: relay.to_string(), down_secs: 300 })
+        );
+    }
+
+    #[test]
+    fn short_blips_are_not_reported() {
+        let t0 = Timestamp::from(1_000_000);
+        let mut health = RelayHealth::new(Duration::from_secs(60));
+        let relay = "wss://relay.example.com";
+
+        health.update(relay, RelayStatus::Connected, t0);
+        health.update(relay, RelayStatus::Disconnected, t0 + 10u64);
+        assert_eq!(health.update(relay, RelayStatus::Connected, t0 + 20u64), None);
+        assert!(health.check(t0 + 200u64).is_empty());
+    }
+}
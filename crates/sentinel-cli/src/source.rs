@@ -0,0 +1,239 @@
+//! Where `sentinel track` gets its position from.
+
+use async_trait::async_trait;
+use clap::Args;
+use nostr_sdk::prelude::*;
+use sentinel_core::event_builder::LocationData;
+use sentinel_core::geohash_util;
+use serde::Deserialize;
+use std::time::Duration;
+
+mod gpsd;
+pub mod nmea;
+
+pub use gpsd::Gpsd;
+pub use nmea::{Nmea, NmeaLog};
+
+/// How long a `--command` may run before the fix is skipped.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A position reading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub lat: f64,
+    pub lon: f64,
+    /// Radius in meters.
+    pub accuracy: Option<f64>,
+    /// Meters per second.
+    pub speed: Option<f64>,
+    /// Course over ground, degrees from true north.
+    pub course: Option<f64>,
+    /// When the fix was taken, if the source says.
+    pub time: Option<Timestamp>,
+}
+
+impl Fix {
+    pub fn new(lat: f64, lon: f64, accuracy: Option<f64>) -> Self {
+        Self { lat, lon, accuracy, speed: None, course: None, time: None }
+    }
+
+    /// The fix as an unencrypted location record for `d_tag`.
+    pub fn to_location(self, d_tag: &str, precision: u8) -> sentinel_core::Result<LocationData> {
+        Ok(LocationData {
+            geohash: geohash_util::encode(self.lat, self.lon, precision)?,
+            lat: self.lat,
+            lon: self.lon,
+            accuracy: self.accuracy,
+            d_tag: d_tag.to_string(),
+            encrypted: false,
+            timestamp: self.time,
+            speed: self.speed,
+            course: self.course,
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SourceError {
+    #[error("{0}")]
+    Config(String),
+    #[error("could not parse position: {0}")]
+    Parse(String),
+    #[error("command failed: {0}")]
+    Command(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// A position source. `current` returns the latest fix, or `None` while the
+/// source has no usable position.
+#[async_trait]
+pub trait LocationSource: Send {
+    fn name(&self) -> String;
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError>;
+
+    /// Whether the source will not give any more fixes, e.g. a replayed log
+    /// that has run out. `track` stops then.
+    fn finished(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SourceArgs {
+    /// Fixed latitude, for machines that don't move
+    #[arg(long, allow_negative_numbers = true, requires = "lon")]
+    lat: Option<f64>,
+
+    /// Fixed longitude
+    #[arg(long, allow_negative_numbers = true, requires = "lat")]
+    lon: Option<f64>,
+
+    /// Accuracy of the fixed position in meters
+    #[arg(long, requires = "lat")]
+    accuracy: Option<f64>,
+
+    /// Shell command printing "lat lon [accuracy]" or {"lat", "lon", "accuracy"} JSON;
+    /// empty output means no fix
+    #[arg(long)]
+    command: Option<String>,
+
+    /// Read fixes from gpsd ("host:port", default localhost:2947)
+    #[arg(long, num_args = 0..=1, default_missing_value = gpsd::DEFAULT_ADDR)]
+    gpsd: Option<String>,
+
+    /// Read NMEA 0183 from a serial device or stdin ("-"); a log file is
+    /// replayed one fix per interval
+    #[arg(long, value_name = "PATH")]
+    nmea: Option<String>,
+}
+
+impl SourceArgs {
+    pub async fn source(self) -> Result<Box<dyn LocationSource>, SourceError> {
+        let mut sources: Vec<Box<dyn LocationSource>> = Vec::new();
+        if let (Some(lat), Some(lon)) = (self.lat, self.lon) {
+            sources.push(Box::new(Fixed(Fix::new(lat, lon, self.accuracy))));
+        }
+        if let Some(command) = self.command {
+            sources.push(Box::new(Command(command)));
+        }
+        if let Some(addr) = self.gpsd {
+            sources.push(Box::new(Gpsd::spawn(&addr)));
+        }
+        if let Some(path) = self.nmea {
+            let is_log = path != "-"
+                && std::fs::metadata(&path)
+                    .map_err(|e| SourceError::Config(format!("{}: {}", path, e)))?
+                    .is_file();
+            if is_log {
+                sources.push(Box::new(NmeaLog::load(&path).await?));
+            } else {
+                sources.push(Box::new(Nmea::spawn(&path)));
+            }
+        }
+        match sources.len() {
+            1 => Ok(sources.remove(0)),
+            0 => Err(SourceError::Config(
+                "track needs a location source: --lat/--lon, --command, --gpsd or --nmea".into(),
+            )),
+            _ => Err(SourceError::Config("use only one location source".into())),
+        }
+    }
+}
+
+/// The same position every time.
+pub struct Fixed(pub Fix);
+
+#[async_trait]
+impl LocationSource for Fixed {
+    fn name(&self) -> String {
+        format!("fixed position {:.6},{:.6}", self.0.lat, self.0.lon)
+    }
+
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError> {
+        Ok(Some(self.0))
+    }
+}
+
+/// Runs a shell command per reading.
+pub struct Command(pub String);
+
+#[async_trait]
+impl LocationSource for Command {
+    fn name(&self) -> String {
+        format!("command `{}`", self.0)
+    }
+
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError> {
+        let output = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.0)
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(COMMAND_TIMEOUT, output)
+            .await
+            .map_err(|_| SourceError::Command(format!("timed out after {:?}", COMMAND_TIMEOUT)))??;
+        if !output.status.success() {
+            return Err(SourceError::Command(format!(
+                "{}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        parse_fix(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonFix {
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+}
+
+/// Parse "lat lon [accuracy]" (space or comma separated) or a JSON object.
+fn parse_fix(text: &str) -> Result<Option<Fix>, SourceError> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    if text.starts_with('{') {
+        let fix: JsonFix =
+            serde_json::from_str(text).map_err(|e| SourceError::Parse(e.to_string()))?;
+        return Ok(Some(Fix::new(fix.lat, fix.lon, fix.accuracy)));
+    }
+    let numbers = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse::<f64>().map_err(|_| SourceError::Parse(text.to_string())))
+        .collect::<Result<Vec<_>, _>>()?;
+    match numbers[..] {
+        [lat, lon] => Ok(Some(Fix::new(lat, lon, None))),
+        [lat, lon, accuracy] => Ok(Some(Fix::new(lat, lon, Some(accuracy)))),
+        _ => Err(SourceError::Parse(text.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn command_source_parses_output() {
+        let fix = |lat, lon, accuracy| Some(Fix::new(lat, lon, accuracy));
+        assert_eq!(parse_fix("60.17 24.94\n").unwrap(), fix(60.17, 24.94, None));
+        assert_eq!(parse_fix("-33.86,151.21,12").unwrap(), fix(-33.86, 151.21, Some(12.0)));
+        assert_eq!(
+            parse_fix(r#"{"lat": 1.5, "lon": 2.5, "accuracy": 3}"#).unwrap(),
+            fix(1.5, 2.5, Some(3.0))
+        );
+        assert_eq!(parse_fix("  \n").unwrap(), None);
+        assert!(parse_fix("60.17").is_err());
+        assert!(parse_fix("north east").is_err());
+
+        let mut source = Command("echo 60.17 24.94 5".into());
+        assert_eq!(source.current().await.unwrap(), fix(60.17, 24.94, Some(5.0)));
+        let mut failing = Command("echo no gps >&2; exit 3".into());
+        assert!(matches!(failing.current().await, Err(SourceError::Command(e)) if e.contains("no gps")));
+    }
+}
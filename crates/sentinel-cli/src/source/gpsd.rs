@@ -0,0 +1,221 @@
+//! gpsd client: watches the JSON protocol in the background and keeps the
+//! latest TPV fix. Reports without a 2D/3D lock drop the fix.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use super::{Fix, LocationSource, SourceError};
+use crate::datetime;
+
+pub const DEFAULT_ADDR: &str = "localhost:2947";
+
+const WATCH: &str = "?WATCH={\"enable\":true,\"json\":true};\n";
+/// A fix older than this is not reported; gpsd sends TPV about once a second.
+const STALE_AFTER: Duration = Duration::from_secs(30);
+const RECONNECT_AFTER: Duration = Duration::from_secs(5);
+
+/// The TPV fields we use. See gpsd_json(5).
+#[derive(Debug, Deserialize)]
+struct Tpv {
+    class: String,
+    /// 0/1 no fix, 2 2D, 3 3D.
+    #[serde(default)]
+    mode: u8,
+    lat: Option<f64>,
+    lon: Option<f64>,
+    /// Estimated horizontal position error in meters (gpsd 3.20+).
+    eph: Option<f64>,
+    /// Longitude and latitude error estimates in meters.
+    epx: Option<f64>,
+    epy: Option<f64>,
+    /// Meters per second.
+    speed: Option<f64>,
+    /// Course over ground, degrees from true north.
+    track: Option<f64>,
+    time: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Report {
+    Fix(Fix),
+    NoLock,
+}
+
+/// Parse one line from gpsd; `None` for anything but a TPV report.
+fn parse_report(line: &str) -> Option<Report> {
+    let tpv: Tpv = serde_json::from_str(line).ok()?;
+    if tpv.class != "TPV" {
+        return None;
+    }
+    let (Some(lat), Some(lon)) = (tpv.lat, tpv.lon) else {
+        return Some(Report::NoLock);
+    };
+    if tpv.mode < 2 {
+        return Some(Report::NoLock);
+    }
+    let accuracy = tpv.eph.or(match (tpv.epx, tpv.epy) {
+        (Some(x), Some(y)) => Some(x.max(y)),
+        _ => None,
+    });
+    Some(Report::Fix(Fix {
+        lat,
+        lon,
+        accuracy,
+        speed: tpv.speed,
+        course: tpv.track,
+        time: tpv.time.as_deref().and_then(datetime::parse),
+    }))
+}
+
+type Latest = Arc<Mutex<Option<(Fix, Instant)>>>;
+
+/// Latest fix from a gpsd daemon, reconnecting when the connection drops.
+pub struct Gpsd {
+    addr: String,
+    latest: Latest,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Gpsd {
+    pub fn spawn(addr: &str) -> Self {
+        let latest: Latest = Arc::new(Mutex::new(None));
+        let task = tokio::spawn(watch(addr.to_string(), Arc::clone(&latest)));
+        Self { addr: addr.to_string(), latest, task }
+    }
+}
+
+impl Drop for Gpsd {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn watch(addr: String, latest: Latest) {
+    // Report the first failure after each connection, not every retry.
+    let mut report = true;
+    loop {
+        let mut connected = false;
+        let result = read_reports(&addr, &latest, &mut connected).await;
+        *latest.lock().unwrap() = None;
+        if connected {
+            report = true;
+        }
+        if report {
+            match result {
+                Ok(()) => eprintln!("gpsd at {} closed the connection", addr),
+                Err(e) => eprintln!("gpsd at {}: {}", addr, e),
+            }
+            report = false;
+        }
+        tokio::time::sleep(RECONNECT_AFTER).await;
+    }
+}
+
+async fn read_reports(addr: &str, latest: &Latest, connected: &mut bool) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(WATCH.as_bytes()).await?;
+    *connected = true;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        match parse_report(&line) {
+            Some(Report::Fix(fix)) => *latest.lock().unwrap() = Some((fix, Instant::now())),
+            Some(Report::NoLock) => *latest.lock().unwrap() = None,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl LocationSource for Gpsd {
+    fn name(&self) -> String {
+        format!("gpsd at {}", self.addr)
+    }
+
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError> {
+        Ok(self
+            .latest
+            .lock()
+            .unwrap()
+            .filter(|(_, received)| received.elapsed() < STALE_AFTER)
+            .map(|(fix, _)| fix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const TPV_3D: &str = r#"{"class":"TPV","device":"/dev/ttyACM0","mode":3,"time":"2026-10-01T12:30:05.000Z","lat":60.1699,"lon":24.9384,"alt":12.5,"epx":4.1,"epy":5.3,"eph":6.2,"speed":13.4,"track":87.5}"#;
+    const TPV_NO_LOCK: &str = r#"{"class":"TPV","device":"/dev/ttyACM0","mode":1}"#;
+
+    #[test]
+    fn tpv_reports() {
+        let Some(Report::Fix(fix)) = parse_report(TPV_3D) else { panic!("no fix") };
+        assert_eq!((fix.lat, fix.lon, fix.accuracy), (60.1699, 24.9384, Some(6.2)));
+        assert_eq!((fix.speed, fix.course), (Some(13.4), Some(87.5)));
+        assert_eq!(fix.time, datetime::parse("2026-10-01T12:30:05Z"));
+        let location = fix.to_location("car", 8).unwrap();
+        assert_eq!((location.speed, location.course), (Some(13.4), Some(87.5)));
+
+        let older = r#"{"class":"TPV","mode":2,"lat":1.0,"lon":2.0,"epx":4.0,"epy":9.0}"#;
+        let Some(Report::Fix(fix)) = parse_report(older) else { panic!("no fix") };
+        assert_eq!(fix.accuracy, Some(9.0));
+
+        assert_eq!(parse_report(TPV_NO_LOCK), Some(Report::NoLock));
+        // A stale position with mode 1 is still no lock.
+        assert_eq!(parse_report(r#"{"class":"TPV","mode":1,"lat":1.0,"lon":2.0}"#), Some(Report::NoLock));
+        assert_eq!(parse_report(r#"{"class":"SKY","satellites":[]}"#), None);
+        assert_eq!(parse_report("not json"), None);
+    }
+
+    async fn wait_for(gpsd: &mut Gpsd, want: impl Fn(&Option<Fix>) -> bool) -> Option<Fix> {
+        for _ in 0..100 {
+            let fix = gpsd.current().await.unwrap();
+            if want(&fix) {
+                return fix;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("gpsd source never reached the expected state");
+    }
+
+    #[tokio::test]
+    async fn follows_fake_gpsd() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (lose_lock, lock_lost) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            assert_eq!(lines.next_line().await.unwrap().unwrap(), WATCH.trim_end());
+            for line in [
+                r#"{"class":"VERSION","release":"3.25","proto_major":3,"proto_minor":15}"#,
+                r#"{"class":"DEVICES","devices":[{"class":"DEVICE","path":"/dev/ttyACM0"}]}"#,
+                TPV_NO_LOCK,
+                TPV_3D,
+            ] {
+                write.write_all(format!("{}\n", line).as_bytes()).await.unwrap();
+            }
+            lock_lost.await.unwrap();
+            write.write_all(format!("{}\n", TPV_NO_LOCK).as_bytes()).await.unwrap();
+            // Keep the connection open until the client is gone.
+            let _ = lines.next_line().await;
+        });
+
+        let mut gpsd = Gpsd::spawn(&addr);
+        let fix = wait_for(&mut gpsd, Option::is_some).await.unwrap();
+        assert_eq!((fix.lat, fix.lon, fix.accuracy), (60.1699, 24.9384, Some(6.2)));
+
+        lose_lock.send(()).unwrap();
+        wait_for(&mut gpsd, Option::is_none).await;
+        drop(gpsd);
+        server.await.unwrap();
+    }
+}
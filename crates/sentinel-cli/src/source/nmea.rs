@@ -0,0 +1,385 @@
+//! NMEA 0183 input: GGA, RMC and GSA sentences from a serial GPS, a log file
+//! or stdin. Sentences of the same epoch (UTC time of day) are merged into
+//! one fix; the epoch is emitted when the next one starts or input ends.
+
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+use super::{Fix, LocationSource, SourceError};
+use crate::datetime;
+
+/// User equivalent range error in meters: accuracy is estimated as
+/// HDOP × UERE, the usual rule of thumb for consumer receivers.
+pub const UERE_M: f64 = 5.0;
+
+const KNOTS_TO_MPS: f64 = 1852.0 / 3600.0;
+/// A live fix older than this is not reported; receivers send about once a second.
+const STALE_AFTER: Duration = Duration::from_secs(30);
+/// Delay before reopening a device that failed or reached end of input.
+const REOPEN_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Report {
+    Fix(Fix),
+    NoLock,
+}
+
+/// One epoch being assembled.
+#[derive(Debug, Default)]
+struct Epoch {
+    /// Seconds since midnight UTC.
+    time: Option<u32>,
+    position: Option<(f64, f64)>,
+    hdop: Option<f64>,
+    speed: Option<f64>,
+    course: Option<f64>,
+    no_lock: bool,
+    seen: bool,
+}
+
+/// Turns NMEA sentences into reports, one per epoch.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    epoch: Epoch,
+    /// Last date from RMC as (year, month, day).
+    date: Option<(i64, u32, u32)>,
+}
+
+impl Decoder {
+    /// Feed one line; returns the previous epoch's report when this line
+    /// starts a new one. Lines that are not valid GGA/RMC/GSA are ignored.
+    pub fn push(&mut self, line: &str) -> Option<Report> {
+        let fields = sentence_fields(line)?;
+        let kind = fields[0].get(2..)?;
+        let field = |i: usize| fields.get(i).copied().unwrap_or("");
+        match kind {
+            "GGA" => {
+                let time = parse_time(field(1));
+                let done = self.start(time);
+                let quality: u8 = field(6).parse().unwrap_or(0);
+                match parse_position(field(2), field(3), field(4), field(5)) {
+                    Some(position) if quality > 0 => self.epoch.position = Some(position),
+                    _ => self.epoch.no_lock = true,
+                }
+                if let Ok(hdop) = field(8).parse() {
+                    self.epoch.hdop = Some(hdop);
+                }
+                done
+            }
+            "RMC" => {
+                let time = parse_time(field(1));
+                let done = self.start(time);
+                match parse_position(field(3), field(4), field(5), field(6)) {
+                    Some(position) if field(2) == "A" => {
+                        self.epoch.position.get_or_insert(position);
+                    }
+                    _ => self.epoch.no_lock = true,
+                }
+                self.epoch.speed = field(7).parse::<f64>().ok().map(|knots| knots * KNOTS_TO_MPS);
+                self.epoch.course = field(8).parse().ok();
+                if let Some(date) = parse_date(field(9)) {
+                    self.date = Some(date);
+                }
+                done
+            }
+            "GSA" => {
+                // No time of its own; belongs to the epoch in progress.
+                self.epoch.seen = true;
+                if field(2).parse::<u8>().unwrap_or(1) < 2 {
+                    self.epoch.no_lock = true;
+                }
+                if let Ok(hdop) = field(16).parse() {
+                    self.epoch.hdop = Some(hdop);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Report the epoch in progress, if any sentence was seen.
+    pub fn finish(&mut self) -> Option<Report> {
+        let epoch = std::mem::take(&mut self.epoch);
+        if !epoch.seen {
+            return None;
+        }
+        let Some((lat, lon)) = epoch.position.filter(|_| !epoch.no_lock) else {
+            return Some(Report::NoLock);
+        };
+        let time = match (self.date, epoch.time) {
+            (Some((year, month, day)), Some(secs)) => datetime::unix_time(year, month, day, secs),
+            _ => None,
+        };
+        Some(Report::Fix(Fix {
+            lat,
+            lon,
+            accuracy: epoch.hdop.map(|hdop| hdop * UERE_M),
+            speed: epoch.speed,
+            course: epoch.course,
+            time,
+        }))
+    }
+
+    fn start(&mut self, time: Option<u32>) -> Option<Report> {
+        let done = if self.epoch.seen && self.epoch.time.is_some() && self.epoch.time != time {
+            self.finish()
+        } else {
+            None
+        };
+        self.epoch.seen = true;
+        self.epoch.time = self.epoch.time.or(time);
+        done
+    }
+}
+
+/// Split a sentence into fields (talker+type first), checking the checksum
+/// when there is one.
+fn sentence_fields(line: &str) -> Option<Vec<&str>> {
+    let body = line.trim().strip_prefix('$')?;
+    let body = match body.split_once('*') {
+        Some((body, checksum)) => {
+            let expected = u8::from_str_radix(checksum.get(..2)?, 16).ok()?;
+            if body.bytes().fold(0, |sum, b| sum ^ b) != expected {
+                return None;
+            }
+            body
+        }
+        None => body,
+    };
+    let fields: Vec<&str> = body.split(',').collect();
+    (fields[0].len() == 5).then_some(fields)
+}
+
+/// `hhmmss[.ss]` to seconds since midnight.
+fn parse_time(s: &str) -> Option<u32> {
+    let h: u32 = s.get(0..2)?.parse().ok()?;
+    let m: u32 = s.get(2..4)?.parse().ok()?;
+    let sec: u32 = s.get(4..6)?.parse().ok()?;
+    Some(h * 3600 + m * 60 + sec)
+}
+
+/// `ddmmyy` to (year, month, day).
+fn parse_date(s: &str) -> Option<(i64, u32, u32)> {
+    let day: u32 = s.get(0..2)?.parse().ok()?;
+    let month: u32 = s.get(2..4)?.parse().ok()?;
+    let year: i64 = s.get(4..6)?.parse().ok()?;
+    Some((2000 + year, month, day))
+}
+
+/// `ddmm.mmmm,N,dddmm.mmmm,E` to signed degrees. Coordinates that are not
+/// finite or out of range count as no position.
+fn parse_position(lat: &str, ns: &str, lon: &str, ew: &str) -> Option<(f64, f64)> {
+    let degrees = |value: &str| -> Option<f64> {
+        let value: f64 = value.parse().ok()?;
+        let whole = (value / 100.0).trunc();
+        Some(whole + (value - whole * 100.0) / 60.0)
+    };
+    let lat = match ns {
+        "N" => degrees(lat)?,
+        "S" => -degrees(lat)?,
+        _ => return None,
+    };
+    let lon = match ew {
+        "E" => degrees(lon)?,
+        "W" => -degrees(lon)?,
+        _ => return None,
+    };
+    (lat.abs() <= 90.0 && lon.abs() <= 180.0).then_some((lat, lon))
+}
+
+/// Every fix in a log, in order. Epochs without a lock are skipped.
+pub async fn read_fixes(input: impl AsyncBufRead + Unpin) -> std::io::Result<Vec<Fix>> {
+    let mut decoder = Decoder::default();
+    let mut fixes = Vec::new();
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some(Report::Fix(fix)) = decoder.push(&line) {
+            fixes.push(fix);
+        }
+    }
+    if let Some(Report::Fix(fix)) = decoder.finish() {
+        fixes.push(fix);
+    }
+    Ok(fixes)
+}
+
+/// Open `path` for reading; `-` is stdin. Serial devices are read as they
+/// are configured (e.g. `stty -F /dev/ttyUSB0 4800 raw`).
+pub async fn open(path: &str) -> std::io::Result<Box<dyn AsyncBufRead + Send + Unpin>> {
+    if path == "-" {
+        Ok(Box::new(BufReader::new(tokio::io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::new(tokio::fs::File::open(path).await?)))
+    }
+}
+
+type Latest = Arc<Mutex<Option<(Fix, Instant)>>>;
+
+/// Latest fix from a serial device or stdin, read in the background. A
+/// device is reopened when it fails or ends; stdin is read once.
+pub struct Nmea {
+    path: String,
+    latest: Latest,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Nmea {
+    pub fn spawn(path: &str) -> Self {
+        let latest: Latest = Arc::new(Mutex::new(None));
+        let task = tokio::spawn(watch(path.to_string(), Arc::clone(&latest)));
+        Self { path: path.to_string(), latest, task }
+    }
+}
+
+impl Drop for Nmea {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn watch(path: String, latest: Latest) {
+    // Report the first failure after each successful open, not every retry.
+    let mut report = true;
+    loop {
+        let mut opened = false;
+        let result = read_sentences(&path, &latest, &mut opened).await;
+        *latest.lock().unwrap() = None;
+        if path == "-" {
+            if let Err(e) = result {
+                eprintln!("NMEA input {}: {}", path, e);
+            }
+            return;
+        }
+        if opened {
+            report = true;
+        }
+        if report {
+            match result {
+                Ok(()) => eprintln!("NMEA input {} ended; reopening", path),
+                Err(e) => eprintln!("NMEA input {}: {}; reopening", path, e),
+            }
+            report = false;
+        }
+        tokio::time::sleep(REOPEN_AFTER).await;
+    }
+}
+
+async fn read_sentences(path: &str, latest: &Latest, opened: &mut bool) -> std::io::Result<()> {
+    let mut decoder = Decoder::default();
+    let mut lines = open(path).await?.lines();
+    *opened = true;
+    while let Some(line) = lines.next_line().await? {
+        match decoder.push(&line) {
+            Some(Report::Fix(fix)) => *latest.lock().unwrap() = Some((fix, Instant::now())),
+            Some(Report::NoLock) => *latest.lock().unwrap() = None,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl LocationSource for Nmea {
+    fn name(&self) -> String {
+        format!("NMEA from {}", self.path)
+    }
+
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError> {
+        Ok(self
+            .latest
+            .lock()
+            .unwrap()
+            .filter(|(_, received)| received.elapsed() < STALE_AFTER)
+            .map(|(fix, _)| fix))
+    }
+
+    fn finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// Replays a recorded log, one fix per reading.
+pub struct NmeaLog {
+    path: String,
+    fixes: VecDeque<Fix>,
+}
+
+impl NmeaLog {
+    pub async fn load(path: &str) -> Result<Self, SourceError> {
+        let fixes = read_fixes(open(path).await?).await?;
+        if fixes.is_empty() {
+            return Err(SourceError::Parse(format!("no fixes in {}", path)));
+        }
+        Ok(Self { path: path.to_string(), fixes: fixes.into() })
+    }
+}
+
+#[async_trait]
+impl LocationSource for NmeaLog {
+    fn name(&self) -> String {
+        format!("NMEA log {} ({} fixes left)", self.path, self.fixes.len())
+    }
+
+    async fn current(&mut self) -> Result<Option<Fix>, SourceError> {
+        Ok(self.fixes.pop_front())
+    }
+
+    fn finished(&self) -> bool {
+        self.fixes.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = "\
+$GPRMC,123005.00,A,6010.194,N,02456.304,E,5.2,87.5,011026,,,A*6B
+$GPGGA,123005.00,6010.194,N,02456.304,E,1,08,1.2,12.5,M,17.0,M,,*5B
+$GPGSA,A,3,04,05,09,12,,,,,,,,,2.1,1.2,1.7*3F
+$GPGSV,3,1,11,04,40,083,46,05,18,308,41,09,07,344,39,12,77,168,47*78
+$GPRMC,123006.00,V,,,,,,,011026,,,N*7F
+$GPGGA,123006.00,,,,,0,00,99.99,,,,,,*60
+$GNGGA,123007.00,3351.600,S,15112.600,W,2,10,0.9,3.0,M,,,,*23
+$GPGGA,123008.00,6010.194,N,02456.304,E,1,08,1.2,12.5,M,17.0,M,,*00
+$GPGGA,123009.00,9512.000,N,02456.304,E,1,08,1.2,12.5,M,17.0,M,,
+$GPGGA,123010.00,6010.194,N,inf,E,1,08,1.2,12.5,M,17.0,M,,
+$GPRMC,123011.00,A,NaN,N,02456.304,E,5.2,87.5,011026,,,A
+";
+
+    #[tokio::test]
+    async fn decodes_nmea_log() {
+        let mut decoder = Decoder::default();
+        let mut reports: Vec<Report> = LOG.lines().filter_map(|line| decoder.push(line)).collect();
+        reports.extend(decoder.finish());
+        assert_eq!(reports.len(), 6, "{:?}", reports);
+
+        let Report::Fix(fix) = reports[0] else { panic!("no fix") };
+        assert!((fix.lat - 60.1699).abs() < 1e-6 && (fix.lon - 24.9384).abs() < 1e-6);
+        // GSA and GGA agree on HDOP 1.2.
+        assert!((fix.accuracy.unwrap() - 1.2 * UERE_M).abs() < 1e-9);
+        assert!((fix.speed.unwrap() - 5.2 * KNOTS_TO_MPS).abs() < 1e-9);
+        assert_eq!(fix.course, Some(87.5));
+        assert_eq!(fix.time, datetime::parse("2026-10-01T12:30:05Z"));
+
+        assert_eq!(reports[1], Report::NoLock);
+        let Report::Fix(fix) = reports[2] else { panic!("no fix") };
+        assert!((fix.lat + 33.86).abs() < 1e-6 && (fix.lon + 151.21).abs() < 1e-6);
+        assert_eq!(fix.time, datetime::parse("2026-10-01T12:30:07Z"));
+        // The 12:30:08 sentence has a bad checksum; the epochs after it have
+        // unusable coordinates.
+        assert_eq!(reports[3..], [Report::NoLock; 3]);
+
+        // So the log has two fixes.
+        let fixes = read_fixes(LOG.as_bytes()).await.unwrap();
+        assert_eq!(fixes.len(), 2);
+        let mut log = NmeaLog { path: "log".into(), fixes: fixes.into() };
+        assert!(log.current().await.unwrap().is_some() && !log.finished());
+        assert!(log.current().await.unwrap().is_some() && log.finished());
+        assert_eq!(sentence_fields("$GPGGA,1*00"), None);
+        assert!(sentence_fields("$GPGGA,1").is_some());
+    }
+}
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
 
+/// Expiration TTL of latest-location events when none is configured.
+pub const DEFAULT_EXPIRATION_SECS: u64 = 3600;
+
 /// Configuration for location tracking and event publishing.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TrackingConfig {
@@ -9,14 +12,24 @@ pub struct TrackingConfig {
     pub precision: u8,
     /// Whether to encrypt location (kind 30473 vs 30472).
     pub encrypted: bool,
+    /// Send locations as NIP-59 gift wraps (kind 1059) to each recipient
+    /// instead of publishing kind 30472/30473.
+    #[serde(default)]
+    pub gift_wrap: bool,
     /// Recipient pubkeys for encrypted events (hex).
     pub recipient_pubkeys: Vec<String>,
     /// Relay URLs to publish to.
     pub relays: Vec<String>,
     /// The `d` tag identifier (e.g. "phone", "car").
     pub d_tag: String,
-    /// Expiration TTL in seconds (added to current time).
-    pub expiration_secs: u64,
+    /// Expiration TTL in seconds (added to current time). 0 disables expiration.
+    /// Unset: [`DEFAULT_EXPIRATION_SECS`], or no expiration in history mode.
+    #[serde(default)]
+    pub expiration_secs: Option<u64>,
+    /// Publish regular (non-replaceable) kind 1472/1473 events so relays
+    /// keep a track, instead of addressable kind 30472/30473.
+    #[serde(default)]
+    pub history: bool,
 }
 
 impl Default for TrackingConfig {
@@ -25,10 +38,24 @@ impl Default for TrackingConfig {
             interval_secs: 60,
             precision: 8,
             encrypted: false,
+            gift_wrap: false,
             recipient_pubkeys: Vec::new(),
             relays: vec!["wss://zooid.atlantislabs.space".to_string()],
             d_tag: "default".to_string(),
-            expiration_secs: 3600,
+            expiration_secs: None,
+            history: false,
+        }
+    }
+}
+
+impl TrackingConfig {
+    /// The expiration TTL in effect, 0 for none: history is kept unless an
+    /// expiration was set explicitly.
+    pub fn expiration_ttl(&self) -> u64 {
+        match self.expiration_secs {
+            Some(secs) => secs,
+            None if self.history => 0,
+            None => DEFAULT_EXPIRATION_SECS,
         }
     }
 }
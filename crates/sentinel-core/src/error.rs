@@ -0,0 +1,82 @@
+use nostr::nips::nip44;
+use thiserror::Error;
+
+/// Errors returned by sentinel-core.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// The event kind does not match what the caller expected.
+    #[error("Not a kind {expected} event (got kind {actual})")]
+    WrongKind { expected: u16, actual: u16 },
+
+    /// A required tag is absent from the event or decrypted payload.
+    #[error("Missing {0} tag")]
+    MissingTag(&'static str),
+
+    /// Geohash could not be encoded or decoded.
+    #[error("Invalid geohash: {0}")]
+    InvalidGeohash(#[from] geohash::GeohashError),
+
+    /// Accuracy is negative, not finite, or not a number.
+    #[error("Invalid accuracy: {0}")]
+    InvalidAccuracy(String),
+
+    /// NIP-44 encryption failed.
+    #[error("Encryption failed: {0}")]
+    Encryption(nip44::Error),
+
+    /// NIP-44 decryption failed.
+    #[error("Decryption failed: {0}")]
+    Decryption(#[from] nip44::Error),
+
+    /// A public (or secret) key could not be parsed.
+    #[error("Invalid key: {0}")]
+    InvalidPubkey(#[from] nostr::key::Error),
+
+    /// A geofence zone definition is malformed.
+    #[error("Invalid geofence: {0}")]
+    InvalidGeofence(String),
+
+    /// An unknown track export format was requested.
+    #[error("Unknown export format: {0} (expected gpx, geojson or kml)")]
+    InvalidExportFormat(String),
+
+    /// A gift wrap (NIP-59) or its seal is malformed or forged.
+    #[error("Invalid gift wrap: {0}")]
+    GiftWrap(&'static str),
+
+    /// JSON (de)serialization failed.
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    /// Encrypted or gift-wrapped locations need at least one recipient.
+    #[error("No recipients for encrypted location")]
+    NoRecipients,
+
+    /// Event signing failed.
+    #[error("Signing failed: {0}")]
+    Signing(#[from] nostr::event::builder::Error),
+}
+
+impl Error {
+    /// Stable machine-readable code for this error, used by the WASM bindings.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::WrongKind { .. } => "wrong_kind",
+            Self::MissingTag(_) => "missing_tag",
+            Self::InvalidGeohash(_) => "invalid_geohash",
+            Self::InvalidAccuracy(_) => "invalid_accuracy",
+            Self::Encryption(_) => "encryption_failed",
+            Self::Decryption(_) => "decryption_failed",
+            Self::InvalidPubkey(_) => "invalid_pubkey",
+            Self::InvalidGeofence(_) => "invalid_geofence",
+            Self::InvalidExportFormat(_) => "invalid_export_format",
+            Self::GiftWrap(_) => "invalid_gift_wrap",
+            Self::Json(_) => "json",
+            Self::NoRecipients => "no_recipients",
+            Self::Signing(_) => "signing_failed",
+        }
+    }
+}
+
+/// Result type for sentinel-core operations.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
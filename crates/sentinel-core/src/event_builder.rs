@@ -1,9 +1,16 @@
 use nostr::prelude::*;
+use serde::{Deserialize, Serialize};
 use crate::config::TrackingConfig;
+use crate::error::{Error, Result};
 use crate::geohash_util;
+use crate::kinds;
+
+/// Maximum number of seconds gift wrap and seal timestamps are randomized
+/// into the past (two days, as recommended by NIP-59).
+pub const GIFT_WRAP_TIMESTAMP_TWEAK_SECS: u64 = 2 * 24 * 60 * 60;
 
 /// Location data extracted from or to be put into a Nostr event.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LocationData {
     pub geohash: String,
     pub lat: f64,
@@ -12,47 +19,260 @@ pub struct LocationData {
     pub d_tag: String,
     pub encrypted: bool,
     pub timestamp: Option<Timestamp>,
+    /// Meters per second, when the source reported it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// Course over ground in degrees from true north, when reported.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub course: Option<f64>,
 }
 
-/// Build a public location event (kind 30472).
+/// Build a public location event (kind 30472, or 1472 in history mode).
 pub fn build_public_event(
     lat: f64,
     lon: f64,
     accuracy: Option<f64>,
     config: &TrackingConfig,
-) -> Result<EventBuilder, Box<dyn std::error::Error>> {
+) -> Result<EventBuilder> {
+    validate_accuracy(accuracy)?;
     let ghash = geohash_util::encode(lat, lon, config.precision)?;
-    let expiration = Timestamp::from(Timestamp::now().as_u64() + config.expiration_secs);
 
     let mut tags = vec![
         Tag::custom(TagKind::custom("g"), vec![ghash]),
         Tag::identifier(&config.d_tag),
-        Tag::expiration(expiration),
     ];
+    tags.extend(expiration_tag(config));
     if let Some(acc) = accuracy {
         tags.push(Tag::custom(TagKind::custom("accuracy"), vec![acc.to_string()]));
     }
 
-    Ok(EventBuilder::new(Kind::from(30472), "").tags(tags))
+    let kind = kinds::public_location(config.history);
+    Ok(EventBuilder::new(Kind::from(kind), "").tags(tags))
 }
 
-/// Build an encrypted location event (kind 30473).
-/// `encrypted_content` should be the NIP-44 ciphertext.
+/// Build an encrypted location event (kind 30473, or 1473 in history mode).
+/// `encrypted_content` should be the NIP-44 ciphertext. Like
+/// [`build_encrypted_location`], the event is addressed with
+/// [`recipient_d_tag`].
 pub fn build_encrypted_event(
     encrypted_content: &str,
     recipient_pubkey: &str,
     config: &TrackingConfig,
-) -> Result<EventBuilder, Box<dyn std::error::Error>> {
-    let expiration = Timestamp::from(Timestamp::now().as_u64() + config.expiration_secs);
+) -> Result<EventBuilder> {
+    let recipient = PublicKey::from_hex(recipient_pubkey)?;
+    let d_tag = recipient_d_tag(&config.d_tag, &recipient);
+    Ok(encrypted_event_builder(encrypted_content, recipient, &d_tag, config))
+}
+
+/// Build a signed encrypted location event (kind 30473/1473) for one recipient.
+///
+/// Builds the payload, NIP-44 encrypts it to `recipient_pubkey` (hex), tags
+/// and signs it. The event is addressed with [`recipient_d_tag`] so that
+/// events for different recipients never replace each other on relays.
+pub fn build_encrypted_location(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    recipient_pubkey: &str,
+    config: &TrackingConfig,
+) -> Result<Event> {
+    let payload = build_encrypted_payload(lat, lon, accuracy, config.precision)?;
+    let recipient = PublicKey::from_hex(recipient_pubkey)?;
+    encrypt_and_sign(&payload, keys, recipient, config, None)
+}
+
+/// Build one signed encrypted location event (kind 30473/1473) per entry in
+/// `config.recipient_pubkeys`. See [`build_encrypted_location`].
+/// Returns an empty list when there are no recipients.
+pub fn build_encrypted_events(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    config: &TrackingConfig,
+) -> Result<Vec<Event>> {
+    let payload = build_encrypted_payload(lat, lon, accuracy, config.precision)?;
+
+    config
+        .recipient_pubkeys
+        .iter()
+        .map(|hex| encrypt_and_sign(&payload, keys, PublicKey::from_hex(hex)?, config, None))
+        .collect()
+}
+
+/// Build a gift-wrapped location event (NIP-59, kind 1059) for one recipient.
+///
+/// The rumor is an unsigned kind 30472 (1472 in history mode) event authored
+/// by `keys`. It is sealed (kind 13) to the recipient and wrapped with a
+/// one-time key. Seal and wrap timestamps are pushed up to
+/// [`GIFT_WRAP_TIMESTAMP_TWEAK_SECS`] into the past, and the wrap carries
+/// only a `p` tag and the configured `expiration`, so relays learn neither
+/// the author nor the `d` tag.
+pub fn build_gift_wrapped_location(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    recipient_pubkey: &str,
+    config: &TrackingConfig,
+) -> Result<Event> {
     let recipient = PublicKey::from_hex(recipient_pubkey)?;
+    let rumor = gift_wrap_rumor(lat, lon, accuracy, keys, config, None)?;
+    gift_wrap(&rumor, keys, recipient, config)
+}
+
+/// Build one gift-wrapped location event per entry in
+/// `config.recipient_pubkeys`. See [`build_gift_wrapped_location`].
+pub fn build_gift_wrapped_events(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    config: &TrackingConfig,
+) -> Result<Vec<Event>> {
+    let rumor = gift_wrap_rumor(lat, lon, accuracy, keys, config, None)?;
+
+    config
+        .recipient_pubkeys
+        .iter()
+        .map(|hex| gift_wrap(&rumor, keys, PublicKey::from_hex(hex)?, config))
+        .collect()
+}
 
-    let tags = vec![
+/// Build the signed events `config` asks for: one public event, or one
+/// encrypted or gift-wrapped event per recipient.
+pub fn build_location_events(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    config: &TrackingConfig,
+) -> Result<Vec<Event>> {
+    build_location_events_at(lat, lon, accuracy, None, keys, config)
+}
+
+/// [`build_location_events`] for a location taken at `created_at` (now if
+/// `None`), e.g. when importing history. For gift wraps this is the rumor's
+/// time; seal and wrap times stay randomized. Expiration still counts from now.
+pub fn build_location_events_at(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    created_at: Option<Timestamp>,
+    keys: &Keys,
+    config: &TrackingConfig,
+) -> Result<Vec<Event>> {
+    if !config.encrypted && !config.gift_wrap {
+        let mut builder = build_public_event(lat, lon, accuracy, config)?;
+        if let Some(created_at) = created_at {
+            builder = builder.custom_created_at(created_at);
+        }
+        return Ok(vec![sign_event(builder, keys)?]);
+    }
+    if config.recipient_pubkeys.is_empty() {
+        return Err(Error::NoRecipients);
+    }
+    let recipients = config
+        .recipient_pubkeys
+        .iter()
+        .map(PublicKey::from_hex)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if config.gift_wrap {
+        let rumor = gift_wrap_rumor(lat, lon, accuracy, keys, config, created_at)?;
+        recipients.into_iter().map(|recipient| gift_wrap(&rumor, keys, recipient, config)).collect()
+    } else {
+        let payload = build_encrypted_payload(lat, lon, accuracy, config.precision)?;
+        recipients
+            .into_iter()
+            .map(|recipient| encrypt_and_sign(&payload, keys, recipient, config, created_at))
+            .collect()
+    }
+}
+
+/// The per-recipient `d` tag used by [`build_encrypted_events`]:
+/// `<d_tag>:<recipient hex>`.
+pub fn recipient_d_tag(d_tag: &str, recipient: &PublicKey) -> String {
+    format!("{}:{}", d_tag, recipient.to_hex())
+}
+
+fn gift_wrap_rumor(
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    keys: &Keys,
+    config: &TrackingConfig,
+    created_at: Option<Timestamp>,
+) -> Result<String> {
+    let mut builder = build_public_event(lat, lon, accuracy, config)?;
+    if let Some(created_at) = created_at {
+        builder = builder.custom_created_at(created_at);
+    }
+    let mut rumor = builder.build(keys.public_key());
+    rumor.ensure_id();
+    Ok(serde_json::to_string(&rumor)?)
+}
+
+fn gift_wrap(rumor: &str, keys: &Keys, recipient: PublicKey, config: &TrackingConfig) -> Result<Event> {
+    let sealed = nip44::encrypt(keys.secret_key(), &recipient, rumor, nip44::Version::V2)
+        .map_err(Error::Encryption)?;
+    let seal = EventBuilder::new(Kind::Seal, sealed)
+        .custom_created_at(Timestamp::tweaked(0..GIFT_WRAP_TIMESTAMP_TWEAK_SECS))
+        .sign_with_keys(keys)?;
+
+    let ephemeral = Keys::generate();
+    let wrapped = nip44::encrypt(
+        ephemeral.secret_key(),
+        &recipient,
+        serde_json::to_string(&seal)?,
+        nip44::Version::V2,
+    )
+    .map_err(Error::Encryption)?;
+
+    let mut tags = vec![Tag::public_key(recipient)];
+    tags.extend(expiration_tag(config));
+    Ok(EventBuilder::new(Kind::GiftWrap, wrapped)
+        .tags(tags)
+        .custom_created_at(Timestamp::tweaked(0..GIFT_WRAP_TIMESTAMP_TWEAK_SECS))
+        .sign_with_keys(&ephemeral)?)
+}
+
+fn encrypt_and_sign(
+    payload: &str,
+    keys: &Keys,
+    recipient: PublicKey,
+    config: &TrackingConfig,
+    created_at: Option<Timestamp>,
+) -> Result<Event> {
+    let ciphertext = nip44::encrypt(keys.secret_key(), &recipient, payload, nip44::Version::V2)
+        .map_err(Error::Encryption)?;
+    let d_tag = recipient_d_tag(&config.d_tag, &recipient);
+    let mut builder = encrypted_event_builder(&ciphertext, recipient, &d_tag, config);
+    if let Some(created_at) = created_at {
+        builder = builder.custom_created_at(created_at);
+    }
+    sign_event(builder, keys)
+}
+
+fn encrypted_event_builder(
+    encrypted_content: &str,
+    recipient: PublicKey,
+    d_tag: &str,
+    config: &TrackingConfig,
+) -> EventBuilder {
+    let mut tags = vec![
         Tag::public_key(recipient),
-        Tag::identifier(&config.d_tag),
-        Tag::expiration(expiration),
+        Tag::identifier(d_tag),
     ];
+    tags.extend(expiration_tag(config));
+
+    let kind = kinds::encrypted_location(config.history);
+    EventBuilder::new(Kind::from(kind), encrypted_content).tags(tags)
+}
 
-    Ok(EventBuilder::new(Kind::from(30473), encrypted_content).tags(tags))
+fn expiration_tag(config: &TrackingConfig) -> Option<Tag> {
+    let ttl = config.expiration_ttl();
+    (ttl > 0).then(|| Tag::expiration(Timestamp::from(Timestamp::now().as_u64() + ttl)))
 }
 
 /// Prepare the plaintext payload for NIP-44 encryption.
@@ -61,7 +281,8 @@ pub fn build_encrypted_payload(
     lon: f64,
     accuracy: Option<f64>,
     precision: u8,
-) -> Result<String, Box<dyn std::error::Error>> {
+) -> Result<String> {
+    validate_accuracy(accuracy)?;
     let ghash = geohash_util::encode(lat, lon, precision)?;
     let mut tags: Vec<Vec<String>> = vec![vec!["g".to_string(), ghash]];
     if let Some(acc) = accuracy {
@@ -74,10 +295,20 @@ pub fn build_encrypted_payload(
 pub fn sign_event(
     builder: EventBuilder,
     keys: &Keys,
-) -> Result<Event, Box<dyn std::error::Error>> {
+) -> Result<Event> {
     Ok(builder.sign_with_keys(keys)?)
 }
 
+/// Reject accuracy values that cannot be a radius in meters.
+fn validate_accuracy(accuracy: Option<f64>) -> Result<()> {
+    match accuracy {
+        Some(acc) if !acc.is_finite() || acc < 0.0 => {
+            Err(Error::InvalidAccuracy(acc.to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,6 +339,51 @@ mod tests {
         assert_eq!(d_tag.unwrap().as_slice()[1], "default");
     }
 
+    #[test]
+    fn rejects_invalid_accuracy() {
+        let config = TrackingConfig::default();
+        let err = build_public_event(60.17, 24.94, Some(-1.0), &config).unwrap_err();
+        assert!(matches!(err, Error::InvalidAccuracy(_)));
+
+        let err = build_encrypted_payload(60.17, 24.94, Some(f64::NAN), 8).unwrap_err();
+        assert!(matches!(err, Error::InvalidAccuracy(_)));
+    }
+
+    #[test]
+    fn rejects_invalid_recipient() {
+        let config = TrackingConfig::default();
+        let err = build_encrypted_event("ciphertext", "not-a-pubkey", &config).unwrap_err();
+        assert!(matches!(err, Error::InvalidPubkey(_)));
+    }
+
+    #[test]
+    fn history_mode_uses_regular_kinds() {
+        let keys = test_keys();
+        let config = TrackingConfig {
+            history: true,
+            recipient_pubkeys: vec![Keys::generate().public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+
+        let builder = build_public_event(60.17, 24.94, None, &config).unwrap();
+        let event = sign_event(builder, &keys).unwrap();
+        assert_eq!(event.kind, Kind::from(kinds::PUBLIC_LOCATION_HISTORY));
+        assert!(event.kind.is_regular());
+        assert!(event.tags.expiration().is_none());
+
+        let events = build_encrypted_events(60.17, 24.94, None, &keys, &config).unwrap();
+        assert_eq!(events[0].kind, Kind::from(kinds::ENCRYPTED_LOCATION_HISTORY));
+        assert!(events[0].tags.expiration().is_none());
+
+        // An explicit expiration still applies to history; latest locations expire by default.
+        let config = TrackingConfig { expiration_secs: Some(60), ..config };
+        let event = sign_event(build_public_event(60.17, 24.94, None, &config).unwrap(), &keys).unwrap();
+        assert!(event.tags.expiration().is_some());
+        let event = sign_event(build_public_event(60.17, 24.94, None, &TrackingConfig::default()).unwrap(), &keys)
+            .unwrap();
+        assert!(event.tags.expiration().is_some());
+    }
+
     #[test]
     fn build_encrypted_payload_json() {
         let payload = build_encrypted_payload(60.17, 24.94, Some(5.0), 8).unwrap();
@@ -142,6 +418,152 @@ mod tests {
             t.as_slice().first().map(|s| s == "p").unwrap_or(false)
         });
         assert!(p_tag.is_some());
+        assert_eq!(
+            event.tags.identifier(),
+            Some(recipient_d_tag(&config.d_tag, &recipient_keys.public_key()).as_str())
+        );
+    }
+
+    #[test]
+    fn build_encrypted_location_encrypts_to_recipient() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let config = TrackingConfig::default();
+
+        let event = build_encrypted_location(
+            60.17,
+            24.94,
+            Some(5.0),
+            &sender,
+            &receiver.public_key().to_hex(),
+            &config,
+        ).unwrap();
+
+        assert_eq!(event.kind, Kind::from(30473));
+        assert!(event.verify().is_ok());
+
+        let decrypted = nip44::decrypt(
+            receiver.secret_key(),
+            &sender.public_key(),
+            &event.content,
+        ).unwrap();
+        assert_eq!(decrypted, build_encrypted_payload(60.17, 24.94, Some(5.0), 8).unwrap());
+    }
+
+    #[test]
+    fn build_encrypted_events_per_recipient() {
+        let sender = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let config = TrackingConfig {
+            encrypted: true,
+            d_tag: "phone".to_string(),
+            recipient_pubkeys: vec![alice.public_key().to_hex(), bob.public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+
+        let events = build_encrypted_events(60.17, 24.94, Some(10.0), &sender, &config).unwrap();
+        assert_eq!(events.len(), 2);
+
+        for (event, recipient) in events.iter().zip([&alice, &bob]) {
+            assert_eq!(event.kind, Kind::from(30473));
+            assert_eq!(event.pubkey, sender.public_key());
+
+            let d_tag = event.tags.identifier().unwrap();
+            assert_eq!(d_tag, recipient_d_tag("phone", &recipient.public_key()));
+
+            let decrypted = nip44::decrypt(
+                recipient.secret_key(),
+                &sender.public_key(),
+                &event.content,
+            ).unwrap();
+            let tags: Vec<Vec<String>> = serde_json::from_str(&decrypted).unwrap();
+            assert_eq!(tags[0][0], "g");
+        }
+
+        assert!(nip44::decrypt(bob.secret_key(), &sender.public_key(), &events[0].content).is_err());
+    }
+
+    #[test]
+    fn gift_wrap_hides_metadata() {
+        let sender = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let config = TrackingConfig {
+            gift_wrap: true,
+            recipient_pubkeys: vec![alice.public_key().to_hex(), bob.public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+
+        let before = Timestamp::now();
+        let wraps = build_gift_wrapped_events(60.17, 24.94, None, &sender, &config).unwrap();
+        assert_eq!(wraps.len(), 2);
+
+        for (wrap, recipient) in wraps.iter().zip([&alice, &bob]) {
+            assert_eq!(wrap.kind, Kind::GiftWrap);
+            assert_ne!(wrap.pubkey, sender.public_key());
+            assert!(wrap.created_at <= before + 1u64);
+            assert!(wrap.verify().is_ok());
+            assert_eq!(wrap.tags.len(), 2);
+            assert_eq!(wrap.tags.public_keys().next(), Some(&recipient.public_key()));
+            assert!(wrap.tags.expiration().unwrap() > &before);
+        }
+        assert_ne!(wraps[0].pubkey, wraps[1].pubkey);
+
+        // Like other events, wraps in history mode are kept by default.
+        let history = TrackingConfig { history: true, ..config };
+        let wraps = build_gift_wrapped_events(60.17, 24.94, None, &sender, &history).unwrap();
+        assert!(wraps[0].tags.expiration().is_none());
+    }
+
+    #[test]
+    fn build_location_events_follows_config() {
+        let keys = test_keys();
+        let alice = Keys::generate();
+
+        let public = build_location_events(60.17, 24.94, None, &keys, &TrackingConfig::default()).unwrap();
+        assert_eq!(public.len(), 1);
+        assert_eq!(public[0].kind, Kind::from(30472));
+        assert!(public[0].verify().is_ok());
+
+        let mut config = TrackingConfig {
+            encrypted: true,
+            recipient_pubkeys: vec![alice.public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+        let encrypted = build_location_events(60.17, 24.94, None, &keys, &config).unwrap();
+        assert_eq!(encrypted[0].kind, Kind::from(30473));
+
+        config.gift_wrap = true;
+        let wrapped = build_location_events(60.17, 24.94, None, &keys, &config).unwrap();
+        assert_eq!(wrapped[0].kind, Kind::GiftWrap);
+
+        config.recipient_pubkeys.clear();
+        let err = build_location_events(60.17, 24.94, None, &keys, &config).unwrap_err();
+        assert_eq!(err.code(), "no_recipients");
+    }
+
+    #[test]
+    fn build_location_events_at_keeps_the_time() {
+        let keys = test_keys();
+        let alice = Keys::generate();
+        let then = Timestamp::from(1_500_000_000);
+        let mut config = TrackingConfig { history: true, ..TrackingConfig::default() };
+
+        let public = build_location_events_at(60.17, 24.94, None, Some(then), &keys, &config).unwrap();
+        assert_eq!(public[0].created_at, then);
+        assert!(public[0].verify().is_ok());
+
+        config.encrypted = true;
+        config.recipient_pubkeys = vec![alice.public_key().to_hex()];
+        let encrypted = build_location_events_at(60.17, 24.94, None, Some(then), &keys, &config).unwrap();
+        assert_eq!(encrypted[0].created_at, then);
+
+        config.gift_wrap = true;
+        let wrapped = build_location_events_at(60.17, 24.94, None, Some(then), &keys, &config).unwrap();
+        assert_ne!(wrapped[0].created_at, then);
+        let location = crate::event_parser::unwrap_gift_wrap(&wrapped[0], &alice).unwrap();
+        assert_eq!(location.timestamp, then);
     }
 
     #[test]
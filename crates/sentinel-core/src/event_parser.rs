@@ -1,8 +1,11 @@
 use nostr::prelude::*;
+use serde::{Deserialize, Serialize};
+use crate::error::{Error, Result};
 use crate::geohash_util;
+use crate::kinds;
 
 /// Parsed location from a Nostr event.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ParsedLocation {
     pub geohash: String,
     pub lat: f64,
@@ -12,23 +15,42 @@ pub struct ParsedLocation {
     pub timestamp: Timestamp,
     pub kind: u16,
     pub pubkey: String,
+    /// Meters per second, when the source reported it (not part of events).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// Course over ground in degrees from true north (not part of events).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub course: Option<f64>,
 }
 
-/// Parse a public location event (kind 30472).
-pub fn parse_public_event(event: &Event) -> Result<ParsedLocation, Box<dyn std::error::Error>> {
-    if event.kind != Kind::from(30472) {
-        return Err("Not a kind 30472 event".into());
-    }
+/// Parse a public location event (kind 30472 or history kind 1472).
+pub fn parse_public_event(event: &Event) -> Result<ParsedLocation> {
+    let kind = expect_kind(event, kinds::PUBLIC_LOCATION, kinds::PUBLIC_LOCATION_HISTORY)?;
+    location_from_tags(&event.tags, event.created_at, kind, &event.pubkey)
+}
 
-    let geohash = find_tag_value(event, "g")
-        .ok_or("Missing g (geohash) tag")?;
+/// Parse an encrypted location event (kind 30473 or history kind 1473).
+/// Requires the decrypted content (plaintext tag array JSON).
+pub fn parse_encrypted_content(
+    event: &Event,
+    decrypted_content: &str,
+) -> Result<ParsedLocation> {
+    let kind = expect_kind(event, kinds::ENCRYPTED_LOCATION, kinds::ENCRYPTED_LOCATION_HISTORY)?;
 
-    let d_tag = find_tag_value(event, "d")
-        .unwrap_or_default();
+    let tags: Vec<Vec<String>> = serde_json::from_str(decrypted_content)?;
 
-    let accuracy = find_tag_value(event, "accuracy")
-        .and_then(|v| v.parse::<f64>().ok());
+    let geohash = tags.iter()
+        .find(|t| t.first().map(|s| s == "g").unwrap_or(false))
+        .and_then(|t| t.get(1))
+        .ok_or(Error::MissingTag("g"))?
+        .clone();
+
+    let accuracy = tags.iter()
+        .find(|t| t.first().map(|s| s == "accuracy").unwrap_or(false))
+        .and_then(|t| t.get(1))
+        .and_then(|v| parse_accuracy(v));
 
+    let d_tag = base_d_tag(event);
     let (lat, lon) = geohash_util::decode(&geohash)?;
 
     Ok(ParsedLocation {
@@ -38,35 +60,84 @@ pub fn parse_public_event(event: &Event) -> Result<ParsedLocation, Box<dyn std::
         accuracy,
         d_tag,
         timestamp: event.created_at,
-        kind: 30472,
+        kind,
         pubkey: event.pubkey.to_hex(),
+        speed: None,
+        course: None,
     })
 }
 
-/// Parse an encrypted location event (kind 30473).
-/// Requires the decrypted content (plaintext tag array JSON).
-pub fn parse_encrypted_content(
-    event: &Event,
-    decrypted_content: &str,
-) -> Result<ParsedLocation, Box<dyn std::error::Error>> {
-    if event.kind != Kind::from(30473) {
-        return Err("Not a kind 30473 event".into());
+/// Decrypt and parse an encrypted location event (kind 30473 or 1473).
+///
+/// `keys` may belong to either the recipient or the author: the NIP-44
+/// conversation key is shared, so authors can read back their own events.
+pub fn decrypt_and_parse(event: &Event, keys: &Keys) -> Result<ParsedLocation> {
+    expect_kind(event, kinds::ENCRYPTED_LOCATION, kinds::ENCRYPTED_LOCATION_HISTORY)?;
+
+    let counterparty = if event.pubkey == keys.public_key() {
+        let p = find_tag_value(&event.tags, "p").ok_or(Error::MissingTag("p"))?;
+        PublicKey::from_hex(&p)?
+    } else {
+        event.pubkey
+    };
+
+    let decrypted = nip44::decrypt(keys.secret_key(), &counterparty, &event.content)?;
+    parse_encrypted_content(event, &decrypted)
+}
+
+/// Unwrap and parse a gift-wrapped location (NIP-59, kind 1059) sent to `keys`.
+///
+/// The result carries the real author (the seal signer) and the rumor's
+/// `created_at` rather than the randomized wrapper values. `kind` is 1059.
+/// Gift wraps that hold something other than a location rumor fail with
+/// [`Error::WrongKind`].
+pub fn unwrap_gift_wrap(event: &Event, keys: &Keys) -> Result<ParsedLocation> {
+    if event.kind != Kind::GiftWrap {
+        return Err(Error::WrongKind { expected: 1059, actual: event.kind.as_u16() });
     }
 
-    let tags: Vec<Vec<String>> = serde_json::from_str(decrypted_content)?;
+    let seal_json = nip44::decrypt(keys.secret_key(), &event.pubkey, &event.content)?;
+    let seal: Event = serde_json::from_str(&seal_json)?;
+    if seal.kind != Kind::Seal {
+        return Err(Error::GiftWrap("seal is not kind 13"));
+    }
+    seal.verify().map_err(|_| Error::GiftWrap("invalid seal signature"))?;
 
-    let geohash = tags.iter()
-        .find(|t| t.first().map(|s| s == "g").unwrap_or(false))
-        .and_then(|t| t.get(1))
-        .ok_or("Missing g tag in decrypted content")?
-        .clone();
+    let rumor_json = nip44::decrypt(keys.secret_key(), &seal.pubkey, &seal.content)?;
+    let rumor: UnsignedEvent = serde_json::from_str(&rumor_json)?;
+    if rumor.pubkey != seal.pubkey {
+        return Err(Error::GiftWrap("rumor author does not match seal"));
+    }
+    let rumor_kind = rumor.kind.as_u16();
+    if rumor_kind != kinds::PUBLIC_LOCATION && rumor_kind != kinds::PUBLIC_LOCATION_HISTORY {
+        return Err(Error::WrongKind { expected: kinds::PUBLIC_LOCATION, actual: rumor_kind });
+    }
 
-    let accuracy = tags.iter()
-        .find(|t| t.first().map(|s| s == "accuracy").unwrap_or(false))
-        .and_then(|t| t.get(1))
-        .and_then(|v| v.parse::<f64>().ok());
+    location_from_tags(&rumor.tags, rumor.created_at, 1059, &rumor.pubkey)
+}
+
+/// The event's `d` tag with any per-recipient suffix
+/// (see [`crate::event_builder::recipient_d_tag`]) removed. Readable without
+/// decrypting, so encrypted events can be attributed to a device.
+pub fn base_d_tag(event: &Event) -> String {
+    strip_recipient_suffix(&event.tags, find_tag_value(&event.tags, "d").unwrap_or_default())
+}
+
+fn location_from_tags(
+    tags: &Tags,
+    timestamp: Timestamp,
+    kind: u16,
+    pubkey: &PublicKey,
+) -> Result<ParsedLocation> {
+    let geohash = find_tag_value(tags, "g")
+        .ok_or(Error::MissingTag("g"))?;
+
+    let d_tag = find_tag_value(tags, "d")
+        .unwrap_or_default();
+
+    let accuracy = find_tag_value(tags, "accuracy")
+        .and_then(|v| parse_accuracy(&v));
 
-    let d_tag = find_tag_value(event, "d").unwrap_or_default();
     let (lat, lon) = geohash_util::decode(&geohash)?;
 
     Ok(ParsedLocation {
@@ -75,14 +146,42 @@ pub fn parse_encrypted_content(
         lon,
         accuracy,
         d_tag,
-        timestamp: event.created_at,
-        kind: 30473,
-        pubkey: event.pubkey.to_hex(),
+        timestamp,
+        kind,
+        pubkey: pubkey.to_hex(),
+        speed: None,
+        course: None,
     })
 }
 
-fn find_tag_value(event: &Event, tag_name: &str) -> Option<String> {
-    event.tags.iter().find_map(|t| {
+/// Accept either the addressable kind or its history counterpart.
+fn expect_kind(event: &Event, addressable: u16, history: u16) -> Result<u16> {
+    let actual = event.kind.as_u16();
+    if actual != addressable && actual != history {
+        return Err(Error::WrongKind { expected: addressable, actual });
+    }
+    Ok(actual)
+}
+
+/// An accuracy tag that is not a finite, non-negative number is ignored, as
+/// if absent: the location itself is still usable.
+fn parse_accuracy(value: &str) -> Option<f64> {
+    value.parse::<f64>().ok().filter(|acc| acc.is_finite() && *acc >= 0.0)
+}
+
+/// Map a per-recipient `d` tag (`<d_tag>:<p hex>`) back to the base identifier.
+fn strip_recipient_suffix(tags: &Tags, d_tag: String) -> String {
+    let suffix = find_tag_value(tags, "p").map(|p| format!(":{}", p));
+    match suffix {
+        Some(suffix) if d_tag.ends_with(&suffix) => {
+            d_tag[..d_tag.len() - suffix.len()].to_string()
+        }
+        _ => d_tag,
+    }
+}
+
+fn find_tag_value(tags: &Tags, tag_name: &str) -> Option<String> {
+    tags.iter().find_map(|t| {
         let s = t.as_slice();
         if s.first().map(|v| v == tag_name).unwrap_or(false) {
             s.get(1).cloned()
@@ -146,4 +245,142 @@ mod tests {
         assert!((parsed.lat - 60.17).abs() < 0.001);
         assert_eq!(parsed.kind, 30473);
     }
+
+    #[test]
+    fn decrypt_and_parse_as_recipient_and_author() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let config = TrackingConfig::default();
+
+        let event = event_builder::build_encrypted_location(
+            60.17,
+            24.94,
+            Some(5.0),
+            &sender,
+            &receiver.public_key().to_hex(),
+            &config,
+        ).unwrap();
+
+        let parsed = decrypt_and_parse(&event, &receiver).unwrap();
+        assert!((parsed.lat - 60.17).abs() < 0.001);
+        assert_eq!(parsed.accuracy, Some(5.0));
+        assert_eq!(parsed.d_tag, "default");
+
+        let own = decrypt_and_parse(&event, &sender).unwrap();
+        assert_eq!(own.geohash, parsed.geohash);
+
+        let err = decrypt_and_parse(&event, &Keys::generate()).unwrap_err();
+        assert!(matches!(err, Error::Decryption(_)));
+    }
+
+    #[test]
+    fn unwrap_gift_wrap_roundtrip() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let config = TrackingConfig {
+            d_tag: "phone".to_string(),
+            gift_wrap: true,
+            ..TrackingConfig::default()
+        };
+
+        let wrap = event_builder::build_gift_wrapped_location(
+            60.17,
+            24.94,
+            Some(12.0),
+            &sender,
+            &receiver.public_key().to_hex(),
+            &config,
+        ).unwrap();
+
+        let parsed = unwrap_gift_wrap(&wrap, &receiver).unwrap();
+        assert!((parsed.lat - 60.17).abs() < 0.001);
+        assert_eq!(parsed.accuracy, Some(12.0));
+        assert_eq!(parsed.d_tag, "phone");
+        assert_eq!(parsed.kind, 1059);
+        assert_eq!(parsed.pubkey, sender.public_key().to_hex());
+        assert!(parsed.timestamp >= wrap.created_at);
+
+        let err = unwrap_gift_wrap(&wrap, &Keys::generate()).unwrap_err();
+        assert!(matches!(err, Error::Decryption(_)));
+    }
+
+    #[test]
+    fn parse_multi_recipient_strips_d_tag_suffix() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let config = TrackingConfig {
+            d_tag: "car".to_string(),
+            recipient_pubkeys: vec![receiver.public_key().to_hex()],
+            ..TrackingConfig::default()
+        };
+
+        let events =
+            event_builder::build_encrypted_events(60.17, 24.94, None, &sender, &config).unwrap();
+
+        let parsed = decrypt_and_parse(&events[0], &receiver).unwrap();
+        assert_eq!(parsed.d_tag, "car");
+    }
+
+    #[test]
+    fn parse_history_events() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let config = TrackingConfig {
+            history: true,
+            ..TrackingConfig::default()
+        };
+
+        let builder = event_builder::build_public_event(60.17, 24.94, None, &config).unwrap();
+        let event = event_builder::sign_event(builder, &sender).unwrap();
+        let parsed = parse_public_event(&event).unwrap();
+        assert_eq!(parsed.kind, kinds::PUBLIC_LOCATION_HISTORY);
+        assert!((parsed.lat - 60.17).abs() < 0.001);
+
+        let event = event_builder::build_encrypted_location(
+            60.17,
+            24.94,
+            None,
+            &sender,
+            &receiver.public_key().to_hex(),
+            &config,
+        ).unwrap();
+        let parsed = decrypt_and_parse(&event, &receiver).unwrap();
+        assert_eq!(parsed.kind, kinds::ENCRYPTED_LOCATION_HISTORY);
+        assert_eq!(parsed.d_tag, "default");
+    }
+
+    #[test]
+    fn parse_errors_are_typed() {
+        let config = TrackingConfig::default();
+        let keys = Keys::generate();
+
+        let builder = event_builder::build_public_event(60.17, 24.94, None, &config).unwrap();
+        let event = event_builder::sign_event(builder, &keys).unwrap();
+        let err = parse_encrypted_content(&event, "[]").unwrap_err();
+        assert!(matches!(err, Error::WrongKind { expected: 30473, actual: 30472 }));
+
+        let event = EventBuilder::new(Kind::from(30472), "")
+            .tags(vec![Tag::identifier("phone")])
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert!(matches!(parse_public_event(&event).unwrap_err(), Error::MissingTag("g")));
+
+        let event = EventBuilder::new(Kind::from(30472), "")
+            .tags(vec![
+                Tag::custom(TagKind::custom("g"), vec!["u4pruydq"]),
+                Tag::custom(TagKind::custom("accuracy"), vec!["far"]),
+            ])
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert_eq!(parse_public_event(&event).unwrap().accuracy, None);
+
+        let event = EventBuilder::new(Kind::from(30472), "")
+            .tags(vec![
+                Tag::custom(TagKind::custom("g"), vec!["u4pruydq"]),
+                Tag::custom(TagKind::custom("accuracy"), vec!["-3"]),
+            ])
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert_eq!(parse_public_event(&event).unwrap().accuracy, None);
+    }
 }
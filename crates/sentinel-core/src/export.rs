@@ -0,0 +1,273 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::{Error, Result};
+use crate::event_parser::ParsedLocation;
+
+/// Track file formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// GPX 1.1: one `trk` per device, `trkpt`s with `time`.
+    Gpx,
+    /// GeoJSON FeatureCollection: a LineString per device with two or more
+    /// locations plus a Point per location, with accuracy, speed and course
+    /// properties.
+    GeoJson,
+    /// KML 2.2: a LineString placemark per device with two or more locations
+    /// plus a Point per location, with accuracy, speed and course as
+    /// ExtendedData.
+    Kml,
+}
+
+impl ExportFormat {
+    /// The usual file extension.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gpx => "gpx",
+            Self::GeoJson => "geojson",
+            Self::Kml => "kml",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "gpx" => Ok(Self::Gpx),
+            "geojson" | "json" => Ok(Self::GeoJson),
+            "kml" => Ok(Self::Kml),
+            _ => Err(Error::InvalidExportFormat(s.to_string())),
+        }
+    }
+}
+
+/// Serialize `locations` as one track per device (pubkey and `d` tag),
+/// each in timestamp order.
+pub fn export(format: ExportFormat, locations: &[ParsedLocation]) -> String {
+    let tracks = tracks(locations);
+    match format {
+        ExportFormat::Gpx => to_gpx(&tracks),
+        ExportFormat::GeoJson => to_geojson(&tracks),
+        ExportFormat::Kml => to_kml(&tracks),
+    }
+}
+
+/// A device's locations in timestamp order.
+struct Track<'a> {
+    name: String,
+    points: Vec<&'a ParsedLocation>,
+}
+
+fn tracks(locations: &[ParsedLocation]) -> Vec<Track<'_>> {
+    let mut tracks: Vec<(&str, &str, Vec<&ParsedLocation>)> = Vec::new();
+    for loc in locations {
+        match tracks.iter_mut().find(|(pk, d, _)| *pk == loc.pubkey && *d == loc.d_tag) {
+            Some((_, _, points)) => points.push(loc),
+            None => tracks.push((&loc.pubkey, &loc.d_tag, vec![loc])),
+        }
+    }
+    let several_authors = tracks.iter().any(|(pk, _, _)| *pk != tracks[0].0);
+    tracks
+        .into_iter()
+        .map(|(pubkey, d_tag, mut points)| {
+            points.sort_by_key(|loc| loc.timestamp);
+            let name = match (several_authors, pubkey.get(..8)) {
+                (true, Some(short)) => format!("{} ({})", d_tag, short),
+                _ => d_tag.to_string(),
+            };
+            Track { name, points }
+        })
+        .collect()
+}
+
+fn to_gpx(tracks: &[Track]) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"sentinel\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+    ));
+    for track in tracks {
+        let _ = writeln!(out, "  <trk>\n    <name>{}</name>\n    <trkseg>", xml_escape(&track.name));
+        for loc in &track.points {
+            let _ = writeln!(
+                out,
+                "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\"><time>{}</time></trkpt>",
+                loc.lat,
+                loc.lon,
+                loc.timestamp.to_human_datetime()
+            );
+        }
+        out.push_str("    </trkseg>\n  </trk>\n");
+    }
+    out.push_str("</gpx>\n");
+    out
+}
+
+fn to_geojson(tracks: &[Track]) -> String {
+    let mut features = Vec::new();
+    for track in tracks {
+        // A LineString needs two positions (RFC 7946 3.1.4).
+        if track.points.len() >= 2 {
+            let first = track.points[0];
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "LineString",
+                    "coordinates": track.points.iter().map(|loc| [loc.lon, loc.lat]).collect::<Vec<_>>(),
+                },
+                "properties": {
+                    "name": track.name,
+                    "d_tag": first.d_tag,
+                    "pubkey": first.pubkey,
+                    "start": first.timestamp.as_u64(),
+                    "end": track.points[track.points.len() - 1].timestamp.as_u64(),
+                },
+            }));
+        }
+        for loc in &track.points {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [loc.lon, loc.lat] },
+                "properties": {
+                    "d_tag": loc.d_tag,
+                    "pubkey": loc.pubkey,
+                    "geohash": loc.geohash,
+                    "accuracy": loc.accuracy,
+                    "speed": loc.speed,
+                    "course": loc.course,
+                    "timestamp": loc.timestamp.as_u64(),
+                    "time": loc.timestamp.to_human_datetime(),
+                    "kind": loc.kind,
+                },
+            }));
+        }
+    }
+    let collection = json!({ "type": "FeatureCollection", "features": features });
+    format!("{:#}\n", collection)
+}
+
+fn to_kml(tracks: &[Track]) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n",
+        "<Document>\n",
+    ));
+    for track in tracks {
+        let name = xml_escape(&track.name);
+        if track.points.len() >= 2 {
+            let coordinates: Vec<String> =
+                track.points.iter().map(|loc| format!("{:.7},{:.7}", loc.lon, loc.lat)).collect();
+            let _ = writeln!(
+                out,
+                "  <Placemark>\n    <name>{}</name>\n    <LineString><coordinates>{}</coordinates></LineString>\n  </Placemark>",
+                name,
+                coordinates.join(" ")
+            );
+        }
+        let _ = writeln!(out, "  <Folder>\n    <name>{} points</name>", name);
+        for loc in &track.points {
+            let data: String = [("accuracy", loc.accuracy), ("speed", loc.speed), ("course", loc.course)]
+                .into_iter()
+                .filter_map(|(name, value)| {
+                    value.map(|value| format!("<Data name=\"{}\"><value>{}</value></Data>", name, value))
+                })
+                .collect();
+            let data = if data.is_empty() { data } else { format!("<ExtendedData>{}</ExtendedData>", data) };
+            let _ = writeln!(
+                out,
+                "    <Placemark><TimeStamp><when>{}</when></TimeStamp>{}<Point><coordinates>{:.7},{:.7}</coordinates></Point></Placemark>",
+                loc.timestamp.to_human_datetime(),
+                data,
+                loc.lon,
+                loc.lat
+            );
+        }
+        out.push_str("  </Folder>\n");
+    }
+    out.push_str("</Document>\n</kml>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr::Timestamp;
+
+    fn location(d_tag: &str, lat: f64, lon: f64, ts: u64) -> ParsedLocation {
+        ParsedLocation {
+            geohash: crate::geohash_util::encode(lat, lon, 9).unwrap(),
+            lat,
+            lon,
+            accuracy: Some(12.5),
+            d_tag: d_tag.to_string(),
+            timestamp: Timestamp::from(ts),
+            kind: 1472,
+            pubkey: "ab".repeat(32),
+            speed: None,
+            course: None,
+        }
+    }
+
+    fn locations() -> Vec<ParsedLocation> {
+        vec![
+            ParsedLocation { speed: Some(13.4), course: Some(87.5), ..location("car", 60.2, 24.9, 1_790_857_865) },
+            location("car", 60.1, 24.8, 1_790_857_805),
+            location("<bike>", 1.0, 2.0, 1_790_857_805),
+        ]
+    }
+
+    #[test]
+    fn gpx_tracks_in_time_order() {
+        let gpx = export(ExportFormat::Gpx, &locations());
+        assert!(gpx.contains("<gpx version=\"1.1\""));
+        assert_eq!(gpx.matches("<trk>").count(), 2);
+        let first = gpx.find("lat=\"60.1000000\"").unwrap();
+        let second = gpx.find("lat=\"60.2000000\"").unwrap();
+        assert!(first < second);
+        assert!(gpx.contains("<time>2026-10-01T12:30:05Z</time>"));
+        assert!(gpx.contains("<name>&lt;bike&gt;</name>"));
+    }
+
+    #[test]
+    fn geojson_lines_and_points() {
+        let geojson: serde_json::Value =
+            serde_json::from_str(&export(ExportFormat::GeoJson, &locations())).unwrap();
+        let features = geojson["features"].as_array().unwrap();
+        // The single-point <bike> track has no LineString.
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[0]["geometry"]["type"], "LineString");
+        assert_eq!(features[0]["geometry"]["coordinates"], json!([[24.8, 60.1], [24.9, 60.2]]));
+        assert_eq!(features[1]["geometry"]["type"], "Point");
+        assert_eq!(features[1]["properties"]["accuracy"], 12.5);
+        assert_eq!(features[1]["properties"]["d_tag"], "car");
+        assert_eq!(features[1]["properties"]["speed"], serde_json::Value::Null);
+        assert_eq!(features[2]["properties"]["speed"], 13.4);
+        assert_eq!(features[2]["properties"]["course"], 87.5);
+        assert_eq!(features[3]["geometry"]["type"], "Point");
+        assert_eq!(features[3]["properties"]["d_tag"], "<bike>");
+    }
+
+    #[test]
+    fn kml_and_format_names() {
+        let kml = export(ExportFormat::Kml, &locations());
+        assert!(kml.contains("<coordinates>24.8000000,60.1000000 24.9000000,60.2000000</coordinates>"));
+        assert!(kml.contains("<when>2026-10-01T12:31:05Z</when>"));
+        assert_eq!(kml.matches("<LineString>").count(), 1);
+        assert!(kml.contains("<coordinates>2.0000000,1.0000000</coordinates></Point>"));
+        assert!(kml.contains("<Data name=\"speed\"><value>13.4</value></Data><Data name=\"course\"><value>87.5</value></Data>"));
+        assert_eq!(export(ExportFormat::Kml, &[]).matches("Placemark").count(), 0);
+
+        assert_eq!("GeoJSON".parse::<ExportFormat>().unwrap(), ExportFormat::GeoJson);
+        assert_eq!("kml".parse::<ExportFormat>().unwrap().extension(), "kml");
+        assert!("shp".parse::<ExportFormat>().is_err());
+    }
+}
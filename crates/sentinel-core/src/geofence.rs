@@ -0,0 +1,385 @@
+use std::str::FromStr;
+
+use nostr::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::event_parser::ParsedLocation;
+use crate::geohash_util;
+
+/// A named zone with optional dwell time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Geofence {
+    pub name: String,
+    #[serde(flatten)]
+    pub shape: Shape,
+    /// Report a dwell after staying inside this many seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dwell_secs: Option<u64>,
+}
+
+/// Zone geometry. Coordinates are degrees, distances meters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Shape {
+    Circle { lat: f64, lon: f64, radius_m: f64 },
+    /// Closed ring of `[lat, lon]` vertices (the closing edge is implicit).
+    Polygon { points: Vec<[f64; 2]> },
+    /// Every location whose geohash cell lies within this prefix cell.
+    Geohash { prefix: String },
+}
+
+/// Where a location lies relative to a zone, given its uncertainty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Containment {
+    Inside,
+    Outside,
+    /// The uncertainty circle straddles the boundary.
+    Uncertain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    Enter,
+    Exit,
+    Dwell,
+}
+
+impl TransitionKind {
+    /// The serialized name: `enter`, `exit` or `dwell`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Enter => "enter",
+            Self::Exit => "exit",
+            Self::Dwell => "dwell",
+        }
+    }
+}
+
+/// A zone state change produced by [`GeofenceTracker::update`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transition {
+    pub zone: String,
+    pub kind: TransitionKind,
+    pub location: ParsedLocation,
+}
+
+impl Geofence {
+    /// Classify a location against this zone.
+    ///
+    /// The location is treated as a circle around its (geohash cell center)
+    /// coordinates whose radius is the larger of its `accuracy` and the
+    /// geohash cell radius. It is only `Inside`/`Outside` when that whole
+    /// circle is on one side of the boundary.
+    pub fn contains(&self, location: &ParsedLocation) -> Result<Containment> {
+        let cell = geohash_util::cell_radius_m(&location.geohash)?;
+        let uncertainty = location.accuracy.unwrap_or(0.0).max(cell);
+        let distance = self.signed_distance_m(location.lat, location.lon)?;
+
+        Ok(if distance >= uncertainty {
+            Containment::Inside
+        } else if distance <= -uncertainty {
+            Containment::Outside
+        } else {
+            Containment::Uncertain
+        })
+    }
+
+    /// Distance from the point to the zone boundary in meters; positive
+    /// inside, negative outside.
+    pub fn signed_distance_m(&self, lat: f64, lon: f64) -> Result<f64> {
+        match &self.shape {
+            Shape::Circle { lat: clat, lon: clon, radius_m } => {
+                Ok(radius_m - geohash_util::distance_m(lat, lon, *clat, *clon))
+            }
+            Shape::Polygon { points } => Ok(polygon_signed_distance(points, lat, lon)),
+            Shape::Geohash { prefix } => {
+                let (min_lat, min_lon, max_lat, max_lon) = geohash_util::bounds(prefix)?;
+                let ring = [
+                    [min_lat, min_lon],
+                    [min_lat, max_lon],
+                    [max_lat, max_lon],
+                    [max_lat, min_lon],
+                ];
+                Ok(polygon_signed_distance(&ring, lat, lon))
+            }
+        }
+    }
+
+    fn validate(self) -> Result<Self> {
+        let ok = match &self.shape {
+            Shape::Circle { lat, lon, radius_m } => {
+                lat.abs() <= 90.0 && lon.abs() <= 180.0 && *radius_m > 0.0
+            }
+            Shape::Polygon { points } => points.len() >= 3,
+            Shape::Geohash { prefix } => geohash_util::bounds(prefix).is_ok(),
+        };
+        if ok && !self.name.is_empty() {
+            Ok(self)
+        } else {
+            Err(Error::InvalidGeofence(self.name))
+        }
+    }
+}
+
+/// Parse a zone from the CLI form `<name>=<shape>:<args>[@<dwell secs>]`:
+///
+/// - `home=circle:60.17,24.94,200` (lat, lon, radius in meters)
+/// - `park=polygon:60.1,24.9;60.1,25.0;60.2,25.0`
+/// - `city=geohash:ud9w@600`
+impl FromStr for Geofence {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidGeofence(s.to_string());
+
+        let (name, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (spec, dwell_secs) = match rest.rsplit_once('@') {
+            Some((spec, dwell)) => (spec, Some(dwell.parse().map_err(|_| invalid())?)),
+            None => (rest, None),
+        };
+        let (kind, args) = spec.split_once(':').ok_or_else(invalid)?;
+
+        let numbers = |list: &str| -> Result<Vec<f64>> {
+            list.split(',')
+                .map(|n| n.trim().parse::<f64>().map_err(|_| invalid()))
+                .collect()
+        };
+
+        let shape = match kind {
+            "circle" => match numbers(args)?.as_slice() {
+                [lat, lon, radius_m] => Shape::Circle { lat: *lat, lon: *lon, radius_m: *radius_m },
+                _ => return Err(invalid()),
+            },
+            "polygon" => {
+                let points = args
+                    .split(';')
+                    .map(|pair| match numbers(pair)?.as_slice() {
+                        [lat, lon] => Ok([*lat, *lon]),
+                        _ => Err(invalid()),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Shape::Polygon { points }
+            }
+            "geohash" => Shape::Geohash { prefix: args.trim().to_string() },
+            _ => return Err(invalid()),
+        };
+
+        Geofence { name: name.trim().to_string(), shape, dwell_secs }
+            .validate()
+            .map_err(|_| invalid())
+    }
+}
+
+/// Parse a JSON array of zones, e.g.
+/// `[{"name":"home","type":"circle","lat":60.17,"lon":24.94,"radius_m":200}]`.
+pub fn parse_zones(json: &str) -> Result<Vec<Geofence>> {
+    let zones: Vec<Geofence> = serde_json::from_str(json)?;
+    zones.into_iter().map(Geofence::validate).collect()
+}
+
+#[derive(Debug, Clone, Default)]
+struct ZoneState {
+    inside: Option<bool>,
+    entered_at: Option<Timestamp>,
+    dwell_reported: bool,
+}
+
+/// Turns a stream of locations for one tracked subject into enter, exit and
+/// dwell transitions.
+///
+/// The first determinate location only establishes the baseline; no
+/// transition is reported for it. `Uncertain` locations keep the previous
+/// state, and locations older than the last one seen are ignored.
+#[derive(Debug, Clone)]
+pub struct GeofenceTracker {
+    zones: Vec<Geofence>,
+    states: Vec<ZoneState>,
+    last_seen: Option<Timestamp>,
+}
+
+impl GeofenceTracker {
+    pub fn new(zones: Vec<Geofence>) -> Self {
+        let states = vec![ZoneState::default(); zones.len()];
+        Self { zones, states, last_seen: None }
+    }
+
+    pub fn zones(&self) -> &[Geofence] {
+        &self.zones
+    }
+
+    /// Whether the subject is currently known to be inside `zone`.
+    pub fn is_inside(&self, zone: &str) -> Option<bool> {
+        let idx = self.zones.iter().position(|z| z.name == zone)?;
+        self.states[idx].inside
+    }
+
+    /// Feed the next location and return the transitions it causes.
+    pub fn update(&mut self, location: &ParsedLocation) -> Result<Vec<Transition>> {
+        if self.last_seen.is_some_and(|t| location.timestamp < t) {
+            return Ok(Vec::new());
+        }
+        self.last_seen = Some(location.timestamp);
+
+        let mut transitions = Vec::new();
+        for (zone, state) in self.zones.iter().zip(self.states.iter_mut()) {
+            let inside = match zone.contains(location)? {
+                Containment::Inside => true,
+                Containment::Outside => false,
+                Containment::Uncertain => match state.inside {
+                    Some(inside) => inside,
+                    None => continue,
+                },
+            };
+
+            let kind = match (state.inside, inside) {
+                (Some(false), true) => Some(TransitionKind::Enter),
+                (Some(true), false) => Some(TransitionKind::Exit),
+                _ => None,
+            };
+            if state.inside != Some(inside) {
+                state.entered_at = inside.then_some(location.timestamp);
+                state.dwell_reported = false;
+            }
+            state.inside = Some(inside);
+
+            if let Some(kind) = kind {
+                transitions.push(Transition {
+                    zone: zone.name.clone(),
+                    kind,
+                    location: location.clone(),
+                });
+            }
+
+            if let (Some(dwell), Some(entered), false) =
+                (zone.dwell_secs, state.entered_at, state.dwell_reported)
+            {
+                if location.timestamp.as_u64().saturating_sub(entered.as_u64()) >= dwell {
+                    state.dwell_reported = true;
+                    transitions.push(Transition {
+                        zone: zone.name.clone(),
+                        kind: TransitionKind::Dwell,
+                        location: location.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(transitions)
+    }
+}
+
+/// Signed distance from (lat, lon) to a polygon ring in meters, using a local
+/// equirectangular projection centered on the point.
+fn polygon_signed_distance(ring: &[[f64; 2]], lat: f64, lon: f64) -> f64 {
+    let scale = geohash_util::EARTH_RADIUS_M.to_radians();
+    let cos_lat = lat.to_radians().cos();
+    let project = |p: &[f64; 2]| ((p[1] - lon) * cos_lat * scale, (p[0] - lat) * scale);
+
+    let mut inside = false;
+    let mut min_dist = f64::INFINITY;
+    for i in 0..ring.len() {
+        let (x1, y1) = project(&ring[i]);
+        let (x2, y2) = project(&ring[(i + 1) % ring.len()]);
+
+        if (y1 > 0.0) != (y2 > 0.0) && 0.0 < x1 + (0.0 - y1) * (x2 - x1) / (y2 - y1) {
+            inside = !inside;
+        }
+
+        let (dx, dy) = (x2 - x1, y2 - y1);
+        let len_sq = dx * dx + dy * dy;
+        let t = if len_sq > 0.0 { (-(x1 * dx + y1 * dy) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+        min_dist = min_dist.min((x1 + t * dx).hypot(y1 + t * dy));
+    }
+
+    if inside { min_dist } else { -min_dist }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(lat: f64, lon: f64, accuracy: Option<f64>, ts: u64) -> ParsedLocation {
+        let geohash = geohash_util::encode(lat, lon, 9).unwrap();
+        let (lat, lon) = geohash_util::decode(&geohash).unwrap();
+        ParsedLocation {
+            geohash,
+            lat,
+            lon,
+            accuracy,
+            d_tag: "phone".to_string(),
+            timestamp: Timestamp::from(ts),
+            kind: 30472,
+            pubkey: String::new(),
+            speed: None,
+            course: None,
+        }
+    }
+
+    #[test]
+    fn circle_containment_uses_accuracy() {
+        let home: Geofence = "home=circle:60.17,24.94,200".parse().unwrap();
+
+        assert_eq!(home.contains(&location(60.17, 24.94, Some(10.0), 0)).unwrap(), Containment::Inside);
+        assert_eq!(home.contains(&location(60.17, 24.94, Some(500.0), 0)).unwrap(), Containment::Uncertain);
+        assert_eq!(home.contains(&location(60.20, 24.94, Some(10.0), 0)).unwrap(), Containment::Outside);
+    }
+
+    #[test]
+    fn polygon_and_geohash_zones() {
+        let park: Geofence = "park=polygon:60.16,24.93;60.16,24.95;60.18,24.95;60.18,24.93"
+            .parse()
+            .unwrap();
+        assert_eq!(park.contains(&location(60.17, 24.94, None, 0)).unwrap(), Containment::Inside);
+        assert_eq!(park.contains(&location(60.19, 24.94, None, 0)).unwrap(), Containment::Outside);
+
+        let cell = geohash_util::encode(60.17, 24.94, 5).unwrap();
+        let city: Geofence = format!("city=geohash:{}", cell).parse().unwrap();
+        let (lat, lon) = geohash_util::decode(&cell).unwrap();
+        assert_eq!(city.contains(&location(lat, lon, None, 0)).unwrap(), Containment::Inside);
+        assert_eq!(city.contains(&location(lat + 1.0, lon, None, 0)).unwrap(), Containment::Outside);
+    }
+
+    #[test]
+    fn parse_rejects_bad_zones() {
+        assert!("home".parse::<Geofence>().is_err());
+        assert!("home=circle:60.17,24.94".parse::<Geofence>().is_err());
+        assert!("home=circle:60.17,24.94,-5".parse::<Geofence>().is_err());
+        assert!("park=polygon:60.1,24.9;60.2,25.0".parse::<Geofence>().is_err());
+        assert!("x=square:1,2,3".parse::<Geofence>().is_err());
+
+        let zones = parse_zones(
+            r#"[{"name":"home","type":"circle","lat":60.17,"lon":24.94,"radius_m":200,"dwell_secs":60}]"#,
+        ).unwrap();
+        assert_eq!(zones[0].dwell_secs, Some(60));
+    }
+
+    #[test]
+    fn tracker_reports_enter_exit_dwell() {
+        let home: Geofence = "home=circle:60.17,24.94,200@300".parse().unwrap();
+        let mut tracker = GeofenceTracker::new(vec![home]);
+
+        // Baseline: outside, nothing reported.
+        assert!(tracker.update(&location(60.20, 24.94, Some(10.0), 100)).unwrap().is_empty());
+
+        let t = tracker.update(&location(60.17, 24.94, Some(10.0), 200)).unwrap();
+        assert_eq!(t.len(), 1);
+        assert_eq!(t[0].kind, TransitionKind::Enter);
+        assert_eq!(t[0].zone, "home");
+
+        // Uncertain keeps state; stale locations are ignored.
+        assert!(tracker.update(&location(60.17, 24.94, Some(1000.0), 300)).unwrap().is_empty());
+        assert!(tracker.update(&location(60.20, 24.94, Some(10.0), 150)).unwrap().is_empty());
+        assert_eq!(tracker.is_inside("home"), Some(true));
+
+        let t = tracker.update(&location(60.17, 24.94, Some(10.0), 500)).unwrap();
+        assert_eq!(t.len(), 1);
+        assert_eq!(t[0].kind, TransitionKind::Dwell);
+        assert!(tracker.update(&location(60.17, 24.94, Some(10.0), 900)).unwrap().is_empty());
+
+        let t = tracker.update(&location(60.20, 24.94, Some(10.0), 1000)).unwrap();
+        assert_eq!(t[0].kind, TransitionKind::Exit);
+    }
+}
@@ -1,17 +1,43 @@
 use geohash::Coord;
+use crate::error::Result;
 
 /// Encode latitude/longitude to a geohash string at the given precision.
-pub fn encode(lat: f64, lon: f64, precision: u8) -> Result<String, geohash::GeohashError> {
+pub fn encode(lat: f64, lon: f64, precision: u8) -> Result<String> {
     let coord = Coord { x: lon, y: lat };
-    geohash::encode(coord, precision as usize)
+    Ok(geohash::encode(coord, precision as usize)?)
 }
 
 /// Decode a geohash string back to (lat, lon) center point.
-pub fn decode(hash: &str) -> Result<(f64, f64), geohash::GeohashError> {
+pub fn decode(hash: &str) -> Result<(f64, f64)> {
     let (coord, _, _) = geohash::decode(hash)?;
     Ok((coord.y, coord.x))
 }
 
+/// Decode a geohash string to its cell bounds: (min_lat, min_lon, max_lat, max_lon).
+pub fn bounds(hash: &str) -> Result<(f64, f64, f64, f64)> {
+    let rect = geohash::decode_bbox(hash)?;
+    Ok((rect.min().y, rect.min().x, rect.max().y, rect.max().x))
+}
+
+/// Half the diagonal of a geohash cell in meters: the worst-case error of
+/// using the cell center as the location.
+pub fn cell_radius_m(hash: &str) -> Result<f64> {
+    let (min_lat, min_lon, max_lat, max_lon) = bounds(hash)?;
+    Ok(distance_m(min_lat, min_lon, max_lat, max_lon) / 2.0)
+}
+
+/// Great-circle (haversine) distance between two points in meters.
+pub fn distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let dphi = (lat2 - lat1).to_radians();
+    let dlambda = (lon2 - lon1).to_radians();
+    let a = (dphi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (dlambda / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().atan2((1.0 - a).sqrt())
+}
+
+/// Mean Earth radius in meters.
+pub const EARTH_RADIUS_M: f64 = 6_371_008.8;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,4 +61,19 @@ mod tests {
         assert_eq!(h4.len(), 4);
         assert_eq!(h12.len(), 12);
     }
+
+    #[test]
+    fn distance_helsinki_tallinn() {
+        let d = distance_m(60.1699, 24.9384, 59.4370, 24.7536);
+        assert!((d - 82_000.0).abs() < 1_000.0);
+        assert_eq!(distance_m(60.0, 25.0, 60.0, 25.0), 0.0);
+    }
+
+    #[test]
+    fn cell_radius_shrinks_with_precision() {
+        let r5 = cell_radius_m("u4pru").unwrap();
+        let r8 = cell_radius_m("u4pruydq").unwrap();
+        assert!(r5 > 2_000.0 && r5 < 4_000.0);
+        assert!(r8 < 20.0);
+    }
 }
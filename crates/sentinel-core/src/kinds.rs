@@ -0,0 +1,26 @@
+/// Public location, addressable (relays keep the latest per `d` tag).
+pub const PUBLIC_LOCATION: u16 = 30472;
+/// Encrypted location, addressable (relays keep the latest per `d` tag).
+pub const ENCRYPTED_LOCATION: u16 = 30473;
+/// Public location, regular (relays keep every event, forming a track).
+pub const PUBLIC_LOCATION_HISTORY: u16 = 1472;
+/// Encrypted location, regular (relays keep every event, forming a track).
+pub const ENCRYPTED_LOCATION_HISTORY: u16 = 1473;
+
+/// Public location kind for addressable or history mode.
+pub fn public_location(history: bool) -> u16 {
+    if history { PUBLIC_LOCATION_HISTORY } else { PUBLIC_LOCATION }
+}
+
+/// Encrypted location kind for addressable or history mode.
+pub fn encrypted_location(history: bool) -> u16 {
+    if history { ENCRYPTED_LOCATION_HISTORY } else { ENCRYPTED_LOCATION }
+}
+
+/// Whether `kind` is one of the location kinds (public or encrypted).
+pub fn is_location(kind: u16) -> bool {
+    matches!(
+        kind,
+        PUBLIC_LOCATION | ENCRYPTED_LOCATION | PUBLIC_LOCATION_HISTORY | ENCRYPTED_LOCATION_HISTORY
+    )
+}
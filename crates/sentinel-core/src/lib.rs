@@ -1,7 +1,14 @@
 pub mod config;
+pub mod error;
+pub mod export;
+pub mod geofence;
 pub mod geohash_util;
+pub mod kinds;
+pub mod policy;
 pub mod event_builder;
 pub mod event_parser;
 
 #[cfg(feature = "wasm")]
 pub mod wasm;
+
+pub use error::{Error, Result};
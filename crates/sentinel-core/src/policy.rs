@@ -0,0 +1,189 @@
+use nostr::Timestamp;
+use serde::{Deserialize, Serialize};
+
+use crate::config::TrackingConfig;
+use crate::error::Result;
+use crate::geohash_util;
+
+/// When a tracker should publish a new reading instead of skipping it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PublishPolicy {
+    /// Never publish more often than this, whatever the movement.
+    pub min_interval_secs: u64,
+    /// Publish at least this often, even when standing still (heartbeat).
+    /// 0 disables the heartbeat.
+    pub max_interval_secs: u64,
+    /// Extra distance in meters, on top of the worse of the two accuracies,
+    /// that counts as having moved.
+    pub min_distance_m: f64,
+    /// Geohash precision whose cell change counts as having moved.
+    pub precision: u8,
+}
+
+impl Default for PublishPolicy {
+    fn default() -> Self {
+        Self {
+            min_interval_secs: 0,
+            max_interval_secs: 900,
+            min_distance_m: 0.0,
+            precision: 8,
+        }
+    }
+}
+
+impl PublishPolicy {
+    /// The default policy at the config's geohash precision.
+    pub fn for_config(config: &TrackingConfig) -> Self {
+        Self { precision: config.precision, ..Self::default() }
+    }
+}
+
+/// Why a reading should be published.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishReason {
+    /// Nothing published yet.
+    First,
+    /// The geohash cell at the policy's precision changed.
+    CellChanged,
+    /// Moved farther than the accuracy of the fixes plus `min_distance_m`.
+    Moved,
+    /// `max_interval_secs` passed since the last publish.
+    Heartbeat,
+}
+
+impl PublishReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::First => "first",
+            Self::CellChanged => "cell_changed",
+            Self::Moved => "moved",
+            Self::Heartbeat => "heartbeat",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Published {
+    lat: f64,
+    lon: f64,
+    accuracy: Option<f64>,
+    geohash: String,
+    at: Timestamp,
+}
+
+/// Applies a [`PublishPolicy`] to a stream of readings from one device.
+#[derive(Debug, Clone)]
+pub struct PublishTracker {
+    policy: PublishPolicy,
+    last: Option<Published>,
+}
+
+impl PublishTracker {
+    pub fn new(policy: PublishPolicy) -> Self {
+        Self { policy, last: None }
+    }
+
+    pub fn policy(&self) -> &PublishPolicy {
+        &self.policy
+    }
+
+    /// Whether a reading taken at `now` should be published, and why.
+    pub fn check(
+        &self,
+        lat: f64,
+        lon: f64,
+        accuracy: Option<f64>,
+        now: Timestamp,
+    ) -> Result<Option<PublishReason>> {
+        let geohash = geohash_util::encode(lat, lon, self.policy.precision)?;
+        let Some(last) = &self.last else {
+            return Ok(Some(PublishReason::First));
+        };
+        let elapsed = now.as_u64().saturating_sub(last.at.as_u64());
+        if elapsed < self.policy.min_interval_secs {
+            return Ok(None);
+        }
+        if geohash != last.geohash {
+            return Ok(Some(PublishReason::CellChanged));
+        }
+        let uncertainty = accuracy.unwrap_or(0.0).max(last.accuracy.unwrap_or(0.0));
+        let moved = geohash_util::distance_m(last.lat, last.lon, lat, lon);
+        if moved > uncertainty + self.policy.min_distance_m {
+            return Ok(Some(PublishReason::Moved));
+        }
+        if self.policy.max_interval_secs > 0 && elapsed >= self.policy.max_interval_secs {
+            return Ok(Some(PublishReason::Heartbeat));
+        }
+        Ok(None)
+    }
+
+    /// Remember a reading that was published.
+    pub fn record(&mut self, lat: f64, lon: f64, accuracy: Option<f64>, now: Timestamp) -> Result<()> {
+        let geohash = geohash_util::encode(lat, lon, self.policy.precision)?;
+        self.last = Some(Published { lat, lon, accuracy, geohash, at: now });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(min_interval_secs: u64, max_interval_secs: u64, min_distance_m: f64) -> PublishTracker {
+        PublishTracker::new(PublishPolicy { min_interval_secs, max_interval_secs, min_distance_m, precision: 6 })
+    }
+
+    #[test]
+    fn publishes_on_movement_and_heartbeat() {
+        let mut tracker = tracker(30, 600, 10.0);
+        let t = |secs: u64| Timestamp::from(1_700_000_000 + secs);
+        // The middle of geohash cell "ud9wrc" (precision 6, ~0.6 x 0.6 km).
+        let (lat, lon) = geohash_util::decode("ud9wrc").unwrap();
+
+        assert_eq!(tracker.check(lat, lon, Some(20.0), t(0)).unwrap(), Some(PublishReason::First));
+        tracker.record(lat, lon, Some(20.0), t(0)).unwrap();
+
+        // ~22 m north: within 20 m accuracy + 10 m.
+        assert_eq!(tracker.check(lat + 0.0002, lon, Some(5.0), t(60)).unwrap(), None);
+        // ~56 m north: moved, but not before min_interval.
+        assert_eq!(tracker.check(lat + 0.0005, lon, Some(5.0), t(10)).unwrap(), None);
+        assert_eq!(tracker.check(lat + 0.0005, lon, Some(5.0), t(60)).unwrap(), Some(PublishReason::Moved));
+        // A worse fix explains the same offset.
+        assert_eq!(tracker.check(lat + 0.0005, lon, Some(60.0), t(60)).unwrap(), None);
+
+        assert_eq!(tracker.check(lat, lon, None, t(599)).unwrap(), None);
+        assert_eq!(tracker.check(lat, lon, None, t(600)).unwrap(), Some(PublishReason::Heartbeat));
+        tracker.record(lat, lon, None, t(600)).unwrap();
+        assert_eq!(tracker.check(lat, lon, None, t(660)).unwrap(), None);
+
+        // No heartbeat when disabled.
+        tracker.policy.max_interval_secs = 0;
+        assert_eq!(tracker.check(lat, lon, None, t(100_000)).unwrap(), None);
+    }
+
+    #[test]
+    fn publishes_on_cell_change() {
+        let mut tracker = tracker(0, 0, 1_000.0);
+        let now = Timestamp::from(1_700_000_000);
+        let (lat, lon) = geohash_util::decode("ud9wrc").unwrap();
+        tracker.record(lat, lon, Some(5.0), now).unwrap();
+        // Just across the eastern edge of the cell: a short hop, but a new cell.
+        let (_, _, _, max_lon) = geohash_util::bounds("ud9wrc").unwrap();
+        assert_eq!(
+            tracker.check(lat, max_lon + 0.0001, Some(5.0), now).unwrap(),
+            Some(PublishReason::CellChanged)
+        );
+        assert_eq!(tracker.check(lat, max_lon - 0.0001, Some(5.0), now).unwrap(), None);
+        assert!(tracker.check(91.0, 0.0, None, now).is_err());
+    }
+
+    #[test]
+    fn policy_json_defaults() {
+        let policy: PublishPolicy = serde_json::from_str(r#"{"min_distance_m": 25}"#).unwrap();
+        assert_eq!(policy, PublishPolicy { min_distance_m: 25.0, ..PublishPolicy::default() });
+        let config = TrackingConfig { precision: 5, ..TrackingConfig::default() };
+        assert_eq!(PublishPolicy::for_config(&config).precision, 5);
+    }
+}
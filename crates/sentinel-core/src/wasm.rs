@@ -1,20 +1,33 @@
 use wasm_bindgen::prelude::*;
 use crate::config::TrackingConfig;
+use crate::error::Error;
 use crate::event_builder;
 use crate::event_parser;
+use crate::geofence::{self, GeofenceTracker};
 use crate::geohash_util;
+use crate::policy::{PublishPolicy, PublishTracker};
+use nostr::Timestamp;
 
 #[wasm_bindgen(start)]
 pub fn init() {
     console_error_panic_hook::set_once();
 }
 
+/// Convert a core error into a JS `Error` carrying a stable `code` property.
+impl From<Error> for JsValue {
+    fn from(err: Error) -> Self {
+        let value: JsValue = JsError::new(&err.to_string()).into();
+        let _ = js_sys::Reflect::set(&value, &"code".into(), &err.code().into());
+        value
+    }
+}
+
 #[wasm_bindgen]
-pub fn encode_geohash(lat: f64, lon: f64, precision: u8) -> Result<String, JsError> {
-    geohash_util::encode(lat, lon, precision).map_err(|e| JsError::new(&e.to_string()))
+pub fn encode_geohash(lat: f64, lon: f64, precision: u8) -> Result<String, JsValue> {
+    Ok(geohash_util::encode(lat, lon, precision)?)
 }
 
-/// Build a public location event (kind 30472).
+/// Build a public location event (kind 30472, or 1472 in history mode).
 /// Returns unsigned event template JSON for the TS signer.
 #[wasm_bindgen]
 pub fn build_public_location_event(
@@ -22,13 +35,12 @@ pub fn build_public_location_event(
     lon: f64,
     accuracy: f64,
     config_json: &str,
-) -> Result<String, JsError> {
+) -> Result<String, JsValue> {
     let config: TrackingConfig =
-        serde_json::from_str(config_json).map_err(|e| JsError::new(&e.to_string()))?;
+        serde_json::from_str(config_json).map_err(Error::from)?;
 
     let acc = if accuracy >= 0.0 { Some(accuracy) } else { None };
-    let builder = event_builder::build_public_event(lat, lon, acc, &config)
-        .map_err(|e| JsError::new(&e.to_string()))?;
+    let builder = event_builder::build_public_event(lat, lon, acc, &config)?;
 
     // Use a dummy pubkey to get the unsigned event structure
     let dummy_pk = nostr::PublicKey::from_hex(
@@ -37,7 +49,7 @@ pub fn build_public_location_event(
     let unsigned = builder.build(dummy_pk);
 
     let result = serde_json::json!({
-        "kind": 30472,
+        "kind": unsigned.kind.as_u16(),
         "content": unsigned.content,
         "tags": unsigned.tags.iter().map(|t| t.as_slice().to_vec()).collect::<Vec<_>>(),
     });
@@ -51,25 +63,23 @@ pub fn build_encrypted_payload(
     lon: f64,
     accuracy: f64,
     precision: u8,
-) -> Result<String, JsError> {
+) -> Result<String, JsValue> {
     let acc = if accuracy >= 0.0 { Some(accuracy) } else { None };
-    event_builder::build_encrypted_payload(lat, lon, acc, precision)
-        .map_err(|e| JsError::new(&e.to_string()))
+    Ok(event_builder::build_encrypted_payload(lat, lon, acc, precision)?)
 }
 
-/// Build an encrypted location event (kind 30473).
+/// Build an encrypted location event (kind 30473, or 1473 in history mode).
 /// Returns unsigned event template JSON.
 #[wasm_bindgen]
 pub fn build_encrypted_location_event(
     encrypted_content: &str,
     recipient_pubkey: &str,
     config_json: &str,
-) -> Result<String, JsError> {
+) -> Result<String, JsValue> {
     let config: TrackingConfig =
-        serde_json::from_str(config_json).map_err(|e| JsError::new(&e.to_string()))?;
+        serde_json::from_str(config_json).map_err(Error::from)?;
 
-    let builder = event_builder::build_encrypted_event(encrypted_content, recipient_pubkey, &config)
-        .map_err(|e| JsError::new(&e.to_string()))?;
+    let builder = event_builder::build_encrypted_event(encrypted_content, recipient_pubkey, &config)?;
 
     let dummy_pk = nostr::PublicKey::from_hex(
         "0000000000000000000000000000000000000000000000000000000000000001",
@@ -77,7 +87,7 @@ pub fn build_encrypted_location_event(
     let unsigned = builder.build(dummy_pk);
 
     let result = serde_json::json!({
-        "kind": 30473,
+        "kind": unsigned.kind.as_u16(),
         "content": unsigned.content,
         "tags": unsigned.tags.iter().map(|t| t.as_slice().to_vec()).collect::<Vec<_>>(),
     });
@@ -86,13 +96,58 @@ pub fn build_encrypted_location_event(
 
 /// Parse a public location event JSON, returning location data JSON.
 #[wasm_bindgen]
-pub fn parse_location_event(event_json: &str) -> Result<String, JsError> {
+pub fn parse_location_event(event_json: &str) -> Result<String, JsValue> {
+    let event: nostr::Event =
+        serde_json::from_str(event_json).map_err(Error::from)?;
+
+    let parsed = event_parser::parse_public_event(&event)?;
+    Ok(location_json(&parsed))
+}
+
+/// Build, encrypt and sign a location event (kind 30473) for one recipient.
+/// `secret_key` is hex or nsec. Returns signed event JSON.
+#[wasm_bindgen]
+pub fn build_signed_encrypted_location_event(
+    lat: f64,
+    lon: f64,
+    accuracy: f64,
+    secret_key: &str,
+    recipient_pubkey: &str,
+    config_json: &str,
+) -> Result<String, JsValue> {
+    let config: TrackingConfig =
+        serde_json::from_str(config_json).map_err(Error::from)?;
+    let keys = nostr::Keys::parse(secret_key).map_err(Error::from)?;
+
+    let acc = if accuracy >= 0.0 { Some(accuracy) } else { None };
+    let event = event_builder::build_encrypted_location(
+        lat,
+        lon,
+        acc,
+        &keys,
+        recipient_pubkey,
+        &config,
+    )?;
+    Ok(serde_json::to_string(&event).map_err(Error::from)?)
+}
+
+/// Decrypt and parse an encrypted (kind 30473) or gift-wrapped (kind 1059)
+/// location event JSON. `secret_key` is hex or nsec. Returns location data JSON.
+#[wasm_bindgen]
+pub fn decrypt_location_event(event_json: &str, secret_key: &str) -> Result<String, JsValue> {
     let event: nostr::Event =
-        serde_json::from_str(event_json).map_err(|e| JsError::new(&e.to_string()))?;
+        serde_json::from_str(event_json).map_err(Error::from)?;
+    let keys = nostr::Keys::parse(secret_key).map_err(Error::from)?;
 
-    let parsed = event_parser::parse_public_event(&event)
-        .map_err(|e| JsError::new(&e.to_string()))?;
+    let parsed = if event.kind == nostr::Kind::GiftWrap {
+        event_parser::unwrap_gift_wrap(&event, &keys)?
+    } else {
+        event_parser::decrypt_and_parse(&event, &keys)?
+    };
+    Ok(location_json(&parsed))
+}
 
+fn location_json(parsed: &event_parser::ParsedLocation) -> String {
     let result = serde_json::json!({
         "geohash": parsed.geohash,
         "lat": parsed.lat,
@@ -103,5 +158,83 @@ pub fn parse_location_event(event_json: &str) -> Result<String, JsError> {
         "kind": parsed.kind,
         "pubkey": parsed.pubkey,
     });
-    Ok(result.to_string())
+    result.to_string()
+}
+
+/// Classify a location JSON (as returned by `parse_location_event`) against a
+/// zones JSON array. Returns `{"<zone>": "inside" | "outside" | "uncertain"}`.
+#[wasm_bindgen]
+pub fn check_geofences(zones_json: &str, location_json: &str) -> Result<String, JsValue> {
+    let zones = geofence::parse_zones(zones_json)?;
+    let location: event_parser::ParsedLocation =
+        serde_json::from_str(location_json).map_err(Error::from)?;
+
+    let mut result = serde_json::Map::new();
+    for zone in &zones {
+        let containment = zone.contains(&location)?;
+        result.insert(zone.name.clone(), serde_json::to_value(containment).map_err(Error::from)?);
+    }
+    Ok(serde_json::Value::Object(result).to_string())
+}
+
+/// Enter/exit/dwell detector for one tracked subject.
+#[wasm_bindgen]
+pub struct GeofenceWatcher {
+    tracker: GeofenceTracker,
+}
+
+#[wasm_bindgen]
+impl GeofenceWatcher {
+    /// Create a watcher from a zones JSON array.
+    #[wasm_bindgen(constructor)]
+    pub fn new(zones_json: &str) -> Result<GeofenceWatcher, JsValue> {
+        let zones = geofence::parse_zones(zones_json)?;
+        Ok(GeofenceWatcher { tracker: GeofenceTracker::new(zones) })
+    }
+
+    /// Feed the next location JSON. Returns a JSON array of transitions.
+    pub fn update(&mut self, location_json: &str) -> Result<String, JsValue> {
+        let location: event_parser::ParsedLocation =
+            serde_json::from_str(location_json).map_err(Error::from)?;
+        let transitions = self.tracker.update(&location)?;
+        Ok(serde_json::to_string(&transitions).map_err(Error::from)?)
+    }
+}
+
+/// Significant-movement publish decisions for one device.
+#[wasm_bindgen]
+pub struct PublishGate {
+    tracker: PublishTracker,
+}
+
+#[wasm_bindgen]
+impl PublishGate {
+    /// Create a gate from a `PublishPolicy` JSON object (missing fields take
+    /// their defaults).
+    #[wasm_bindgen(constructor)]
+    pub fn new(policy_json: &str) -> Result<PublishGate, JsValue> {
+        let policy: PublishPolicy = serde_json::from_str(policy_json).map_err(Error::from)?;
+        Ok(PublishGate { tracker: PublishTracker::new(policy) })
+    }
+
+    /// Whether to publish a reading taken at `now_secs` (unix seconds). Returns
+    /// the reason (`first`, `cell_changed`, `moved`, `heartbeat`) or undefined.
+    /// Accuracy < 0 means unknown.
+    pub fn check(
+        &self,
+        lat: f64,
+        lon: f64,
+        accuracy: f64,
+        now_secs: f64,
+    ) -> Result<Option<String>, JsValue> {
+        let acc = if accuracy >= 0.0 { Some(accuracy) } else { None };
+        let reason = self.tracker.check(lat, lon, acc, Timestamp::from(now_secs as u64))?;
+        Ok(reason.map(|r| r.as_str().to_string()))
+    }
+
+    /// Record a reading that was published.
+    pub fn record(&mut self, lat: f64, lon: f64, accuracy: f64, now_secs: f64) -> Result<(), JsValue> {
+        let acc = if accuracy >= 0.0 { Some(accuracy) } else { None };
+        Ok(self.tracker.record(lat, lon, acc, Timestamp::from(now_secs as u64))?)
+    }
 }